@@ -0,0 +1,65 @@
+-- Tables owned by terra in the auth database.
+
+CREATE TABLE IF NOT EXISTS `terra_account_tokens` (
+  `nonce` CHAR(32) NOT NULL,
+  `account` INT UNSIGNED NOT NULL,
+  `purpose` VARCHAR(16) NOT NULL,
+  `email` VARCHAR(255) NULL,
+  `expires_at` INT UNSIGNED NOT NULL,
+  `used_at` INT UNSIGNED NULL,
+  PRIMARY KEY (`nonce`),
+  KEY `idx_account` (`account`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `terra_verified_emails` (
+  `account` INT UNSIGNED NOT NULL,
+  `email` VARCHAR(255) NOT NULL,
+  `verified_at` INT UNSIGNED NOT NULL,
+  PRIMARY KEY (`account`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `terra_invite_codes` (
+  `code` VARCHAR(32) NOT NULL,
+  `created_by` INT UNSIGNED NOT NULL,
+  `created_at` INT UNSIGNED NOT NULL,
+  `expires_at` INT UNSIGNED NULL,
+  `max_uses` INT UNSIGNED NOT NULL,
+  `uses` INT UNSIGNED NOT NULL DEFAULT 0,
+  `realm` INT UNSIGNED NULL,
+  `role` VARCHAR(64) NULL,
+  `block` VARCHAR(64) NULL,
+  `revoked_at` INT UNSIGNED NULL,
+  PRIMARY KEY (`code`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `terra_invite_uses` (
+  `code` VARCHAR(32) NOT NULL,
+  `account` INT UNSIGNED NOT NULL,
+  `used_at` INT UNSIGNED NOT NULL,
+  PRIMARY KEY (`account`),
+  KEY `idx_code` (`code`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Terra permissions for cores with RBAC, ids match the defaults of the rbac
+-- config section. Moderators review characters, gamemasters service them and
+-- administrators manage accounts and the campaign.
+
+INSERT IGNORE INTO `rbac_permissions` (`id`, `name`) VALUES
+  (1000, 'Terra: view accounts'),
+  (1001, 'Terra: manage accounts'),
+  (1002, 'Terra: manage characters'),
+  (1003, 'Terra: view hidden character data'),
+  (1004, 'Terra: approve characters'),
+  (1005, 'Terra: service characters'),
+  (1006, 'Terra: view audit log'),
+  (1007, 'Terra: edit campaign');
+
+INSERT IGNORE INTO `rbac_linked_permissions` (`id`, `linkedId`) VALUES
+  (194, 1003),
+  (194, 1004),
+  (194, 1006),
+  (193, 1002),
+  (193, 1005),
+  (192, 1000),
+  (192, 1001),
+  (192, 1007);
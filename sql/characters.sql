@@ -0,0 +1,34 @@
+-- Tables owned by terra in the characters database.
+
+CREATE TABLE IF NOT EXISTS `terra_character_revisions` (
+  `id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  `guid` INT UNSIGNED NOT NULL,
+  `author` INT UNSIGNED NOT NULL,
+  `info` TEXT NULL,
+  `name_extra` VARCHAR(255) NULL,
+  `approved` TINYINT UNSIGNED NOT NULL DEFAULT 0,
+  `approved_by` INT UNSIGNED NULL,
+  `superseded` TINYINT UNSIGNED NOT NULL DEFAULT 0,
+  `created_at` INT UNSIGNED NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `idx_guid` (`guid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `terra_name_reservations` (
+  `name` VARCHAR(12) NOT NULL,
+  `guid` INT UNSIGNED NOT NULL,
+  `created_at` INT UNSIGNED NOT NULL,
+  PRIMARY KEY (`name`),
+  UNIQUE KEY `idx_guid` (`guid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+CREATE TABLE IF NOT EXISTS `terra_audit_log` (
+  `id` INT UNSIGNED NOT NULL AUTO_INCREMENT,
+  `account` INT UNSIGNED NOT NULL,
+  `action` VARCHAR(64) NOT NULL,
+  `guid` INT UNSIGNED NULL,
+  `details` JSON NULL,
+  `created_at` INT UNSIGNED NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `idx_guid` (`guid`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,25 @@
+-- Skyland cores keep terra's character data in their own columns. This view
+-- gives it the shape of the table stock cores use instead, see
+-- characters_trinity.sql. Character lists filter on the campaign race, class
+-- and role, so those get indexed generated columns; run the ALTER once.
+
+ALTER TABLE `characters`
+  ADD COLUMN `metaRace` VARCHAR(64) AS (JSON_UNQUOTE(JSON_EXTRACT(`metadata`, '$.race'))) VIRTUAL,
+  ADD COLUMN `metaClass` VARCHAR(64) AS (JSON_UNQUOTE(JSON_EXTRACT(`metadata`, '$.class'))) VIRTUAL,
+  ADD COLUMN `metaRole` VARCHAR(64) AS (JSON_UNQUOTE(JSON_EXTRACT(`metadata`, '$.role'))) VIRTUAL,
+  ADD KEY `idx_meta_race` (`metaRace`),
+  ADD KEY `idx_meta_class` (`metaClass`),
+  ADD KEY `idx_meta_role` (`metaRole`);
+
+CREATE OR REPLACE VIEW `terra_character_data` AS
+  SELECT
+    `guid`,
+    `locked`,
+    `stashed`,
+    `nameExtra` AS `name_extra`,
+    `bannedSpells` AS `banned_spells`,
+    `metadata`,
+    `metaRace` AS `meta_race`,
+    `metaClass` AS `meta_class`,
+    `metaRole` AS `meta_role`
+  FROM `characters`;
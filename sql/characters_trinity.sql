@@ -0,0 +1,19 @@
+-- Stock cores have no columns for terra's character data, it is kept here
+-- instead. The core does not read this table: locked characters can still log
+-- in, banned spells are not enforced and the name extra is only shown by terra.
+
+CREATE TABLE IF NOT EXISTS `terra_character_data` (
+  `guid` INT UNSIGNED NOT NULL,
+  `locked` TINYINT UNSIGNED NOT NULL DEFAULT 0,
+  `stashed` TINYINT UNSIGNED NOT NULL DEFAULT 0,
+  `name_extra` VARCHAR(255) NULL,
+  `banned_spells` TEXT NULL,
+  `metadata` JSON NULL,
+  `meta_race` VARCHAR(64) AS (JSON_UNQUOTE(JSON_EXTRACT(`metadata`, '$.race'))) VIRTUAL,
+  `meta_class` VARCHAR(64) AS (JSON_UNQUOTE(JSON_EXTRACT(`metadata`, '$.class'))) VIRTUAL,
+  `meta_role` VARCHAR(64) AS (JSON_UNQUOTE(JSON_EXTRACT(`metadata`, '$.role'))) VIRTUAL,
+  PRIMARY KEY (`guid`),
+  KEY `idx_meta_race` (`meta_race`),
+  KEY `idx_meta_class` (`meta_class`),
+  KEY `idx_meta_role` (`meta_role`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
@@ -0,0 +1,460 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::IpAddr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use http::HeaderMap;
+use ring::{constant_time, digest, hmac};
+use serde::{Deserialize, Deserializer, Serialize};
+use crate::{
+    db,
+    error::{AppError, AppResult},
+    init::CtxRef,
+    util,
+};
+
+const KEY_HEADER: &str = "x-terra-key";
+const TIMESTAMP_HEADER: &str = "x-terra-timestamp";
+const SIGNATURE_HEADER: &str = "x-terra-signature";
+const ACCOUNT_HEADER: &str = "x-terra-account";
+
+/// How far a signed request's timestamp may be off, in seconds.
+const SIGNATURE_WINDOW: u64 = 300;
+
+/// What a caller may do beyond managing their own account and characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Read any account.
+    ViewAccounts,
+    /// Change any account.
+    ManageAccounts,
+    /// Create, edit and rename characters of any account.
+    ManageCharacters,
+    /// See character metadata through the gm visibility rule.
+    ViewHidden,
+    /// Review, approve and revert biography revisions.
+    ApproveCharacters,
+    /// Force renames and other at-login flags.
+    ServiceCharacters,
+    /// Read the audit log.
+    ViewAudit,
+    /// Change campaign definitions.
+    EditCampaign,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 8] = [
+        Permission::ViewAccounts,
+        Permission::ManageAccounts,
+        Permission::ManageCharacters,
+        Permission::ViewHidden,
+        Permission::ApproveCharacters,
+        Permission::ServiceCharacters,
+        Permission::ViewAudit,
+        Permission::EditCampaign,
+    ];
+}
+
+/// Permissions granted from each gmlevel up.
+pub type PermissionMap = BTreeMap<u8, HashSet<Permission>>;
+
+/// Moderators review characters, game masters service them and read accounts,
+/// only administrators change accounts and campaigns.
+pub fn default_permissions() -> PermissionMap {
+    use Permission::*;
+    let mut map = PermissionMap::new();
+    map.insert(1, vec![ViewHidden, ApproveCharacters].into_iter().collect());
+    map.insert(
+        2,
+        vec![ViewAccounts, ManageCharacters, ServiceCharacters, ViewAudit].into_iter().collect(),
+    );
+    map.insert(3, vec![ManageAccounts, EditCampaign].into_iter().collect());
+    map
+}
+
+fn permissions_for(map: &PermissionMap, gmlevel: u8) -> HashSet<Permission> {
+    map.range(..=gmlevel)
+        .flat_map(|(_, permissions)| permissions.iter().cloned())
+        .collect()
+}
+
+/// Reads permissions from the core's RBAC tables instead of the gmlevel map.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RbacConfig {
+    /// Realm whose grants and denials apply to account level actions, the
+    /// first configured one when left out. Realm routes use their own realm.
+    #[serde(default)]
+    pub realm: Option<i32>,
+    /// RBAC permission id standing for each terra permission.
+    #[serde(default = "default_rbac_ids", deserialize_with = "merge_rbac_ids")]
+    pub ids: HashMap<Permission, u32>,
+}
+
+/// Ids from 1000 up in the order of `Permission::ALL`, as in `sql/auth.sql`.
+fn default_rbac_ids() -> HashMap<Permission, u32> {
+    Permission::ALL.iter().cloned().zip(1000..).collect()
+}
+
+/// Configured ids replace the default ones for their permissions only.
+fn merge_rbac_ids<'de, D>(deserializer: D) -> Result<HashMap<Permission, u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut ids = default_rbac_ids();
+    ids.extend(HashMap::<Permission, u32>::deserialize(deserializer)?);
+    Ok(ids)
+}
+
+/// A backend calling terra with a shared secret.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    pub secret: String,
+    /// Permissions the key can exercise, also when acting for an account.
+    #[serde(default)]
+    pub scopes: HashSet<Permission>,
+    /// Whether the key may act on behalf of the account in `X-Terra-Account`.
+    #[serde(default)]
+    pub act_as: bool,
+}
+
+/// Signatures seen within the signature window, so that none is accepted twice.
+#[derive(Default)]
+pub struct ReplayGuard {
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl ReplayGuard {
+    fn check(&self, signature: &str, now: u64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        let expired: Vec<String> = seen
+            .iter()
+            .filter(|(_, &at)| at + 2 * SIGNATURE_WINDOW < now)
+            .map(|(signature, _)| signature.clone())
+            .collect();
+        for signature in expired {
+            seen.remove(&signature);
+        }
+        seen.insert(signature.to_owned(), now).is_none()
+    }
+}
+
+/// An authenticated account or API key making a request. Gmlevel and
+/// permissions are those for account level actions until `on_realm` is used.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    /// Zero for an API key acting on its own.
+    pub account: u32,
+    pub gmlevel: u8,
+    pub permissions: HashSet<Permission>,
+    /// Name of the API key the request was signed with.
+    pub key: Option<String>,
+}
+
+impl Caller {
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    pub fn require(&self, permission: Permission) -> AppResult<()> {
+        if self.has(permission) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden)
+        }
+    }
+
+    /// Passes for the owner of a resource and for callers holding `permission`.
+    pub fn require_owner(&self, owner: u32, permission: Permission) -> AppResult<()> {
+        if self.account == owner {
+            Ok(())
+        } else {
+            self.require(permission)
+        }
+    }
+
+    /// Passes for the account itself and for callers of a higher gmlevel than
+    /// the account's. API keys acting on their own outrank every account.
+    pub async fn require_above(&self, ctx: &CtxRef, account: u32) -> AppResult<()> {
+        if self.account == 0 || self.account == account {
+            return Ok(());
+        }
+        let gmlevel = db::account::gmlevel(ctx.auth_db.clone(), account, account_realm(ctx)).await?;
+        if gmlevel < self.gmlevel {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden)
+        }
+    }
+
+    /// The caller with the gmlevel and permissions it holds on `realm`. API
+    /// keys acting on their own keep their scopes everywhere.
+    pub async fn on_realm(self, ctx: &CtxRef, realm: u32) -> AppResult<Caller> {
+        if self.account == 0 {
+            return Ok(self);
+        }
+        let (gmlevel, permissions) = account_access(ctx, self.account, realm as i32).await?;
+        let permissions = match &self.key {
+            Some(name) => ctx
+                .api_keys
+                .iter()
+                .find(|key| &key.name == name)
+                .map(|key| permissions.intersection(&key.scopes).cloned().collect())
+                .unwrap_or_default(),
+            None => permissions,
+        };
+        Ok(Caller { gmlevel, permissions, ..self })
+    }
+}
+
+/// Parts of a request that authenticate it.
+pub struct Request<'a> {
+    pub method: &'a str,
+    /// Path including the query string.
+    pub path: &'a str,
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+    /// Client address, failed logins are counted by it and the username.
+    pub ip: Option<IpAddr>,
+}
+
+/// Whether the request claims to be signed with an API key. Rate limits are
+/// checked before authenticating and leave such requests to the signature.
+pub fn signed(headers: &HeaderMap) -> bool {
+    headers.contains_key(KEY_HEADER)
+}
+
+/// Identifies the caller from a signed API key request or from an
+/// `Authorization: Basic` header. Requests carrying neither are anonymous.
+pub async fn identify(ctx: &CtxRef, request: &Request<'_>) -> AppResult<Option<Caller>> {
+    let header = |name: &str| {
+        request
+            .headers
+            .get(name)
+            .map(|v| v.to_str().map_err(|_| AppError::Unauthorized))
+    };
+    if let Some(key) = header(KEY_HEADER) {
+        verify_signed(ctx, request, key?).await.map(Some)
+    } else if let Some(authorization) = header(http::header::AUTHORIZATION.as_str()) {
+        authenticate(ctx, authorization?, request.ip).await.map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+async fn authenticate(ctx: &CtxRef, header: &str, ip: Option<IpAddr>) -> AppResult<Caller> {
+    let (username, password) = parse_basic(header).ok_or(AppError::Unauthorized)?;
+    let failures = ctx.rate_limits.failed_login.as_ref();
+    ctx.rate_limiter.check_login(failures, ip, &username)?;
+    let found = db::account::authenticate(ctx.auth_db.clone(), &username, &password).await?;
+    let account = match found {
+        Some(account) => account,
+        None => {
+            ctx.rate_limiter.fail_login(failures, ip, &username);
+            return Err(AppError::Unauthorized);
+        }
+    };
+    db::ban::check(ctx.auth_db.clone(), account.id).await?;
+    let (gmlevel, permissions) = account_access(ctx, account.id, account_realm(ctx)).await?;
+    Ok(Caller {
+        account: account.id,
+        gmlevel,
+        permissions,
+        key: None,
+    })
+}
+
+async fn verify_signed(ctx: &CtxRef, request: &Request<'_>, name: &str) -> AppResult<Caller> {
+    let header = |name: &str| {
+        request
+            .headers
+            .get(name)
+            .map(|v| v.to_str().map_err(|_| AppError::Unauthorized))
+            .transpose()
+    };
+    let key = ctx
+        .api_keys
+        .iter()
+        .find(|key| key.name == name)
+        .ok_or(AppError::Unauthorized)?;
+    let timestamp = header(TIMESTAMP_HEADER)?.ok_or(AppError::Unauthorized)?;
+    let signature = header(SIGNATURE_HEADER)?.ok_or(AppError::Unauthorized)?;
+    let account = header(ACCOUNT_HEADER)?.unwrap_or("");
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let sent: u64 = timestamp.parse().map_err(|_| AppError::Unauthorized)?;
+    if sent + SIGNATURE_WINDOW < now || now + SIGNATURE_WINDOW < sent {
+        return Err(AppError::Unauthorized);
+    }
+    let expected = sign(
+        &key.secret,
+        request.method,
+        request.path,
+        timestamp,
+        account,
+        request.body,
+    );
+    constant_time::verify_slices_are_equal(
+        expected.as_bytes(),
+        signature.to_uppercase().as_bytes(),
+    )
+    .map_err(|_| AppError::Unauthorized)?;
+    if !ctx.replay_guard.check(&expected, now) {
+        return Err(AppError::Unauthorized);
+    }
+
+    if account.is_empty() {
+        return Ok(Caller {
+            account: 0,
+            gmlevel: 0,
+            permissions: key.scopes.clone(),
+            key: Some(key.name.clone()),
+        });
+    }
+    if !key.act_as {
+        return Err(AppError::Forbidden);
+    }
+    let account: u32 = account.parse().map_err(|_| AppError::InvalidInput(ACCOUNT_HEADER))?;
+    match db::account::read(ctx.auth_db.clone(), account).await {
+        Ok(_) => {}
+        Err(AppError::NotFound) => return Err(AppError::Unauthorized),
+        Err(err) => return Err(err),
+    }
+    db::ban::check(ctx.auth_db.clone(), account).await?;
+    let (gmlevel, permissions) = account_access(ctx, account, account_realm(ctx)).await?;
+    Ok(Caller {
+        account,
+        gmlevel,
+        permissions: permissions.intersection(&key.scopes).cloned().collect(),
+        key: Some(key.name.clone()),
+    })
+}
+
+/// Realm whose access applies to account level actions.
+fn account_realm(ctx: &CtxRef) -> i32 {
+    ctx.rbac
+        .as_ref()
+        .and_then(|rbac| rbac.realm)
+        .unwrap_or(ctx.main_realm().id as i32)
+}
+
+/// Gmlevel and permissions of an account on `realm`, counting what is granted
+/// on all realms.
+async fn account_access(
+    ctx: &CtxRef,
+    account: u32,
+    realm: i32,
+) -> AppResult<(u8, HashSet<Permission>)> {
+    let gmlevel = db::account::gmlevel(ctx.auth_db.clone(), account, realm).await?;
+    let permissions = match &ctx.rbac {
+        Some(rbac) => {
+            let granted =
+                db::rbac::account_permissions(ctx.auth_db.clone(), account, realm).await?;
+            rbac.ids
+                .iter()
+                .filter(|(_, id)| granted.contains(id))
+                .map(|(permission, _)| *permission)
+                .collect()
+        }
+        None => permissions_for(&ctx.permissions, gmlevel),
+    };
+    Ok((gmlevel, permissions))
+}
+
+/// HMAC-SHA256 over method, path with query, timestamp, the account acted
+/// for (empty if none) and the SHA256 of the body, each on its own line.
+/// Hashes are written as upper case hex.
+fn sign(
+    secret: &str,
+    method: &str,
+    path: &str,
+    timestamp: &str,
+    account: &str,
+    body: &[u8],
+) -> String {
+    let body_hash = util::hexstring(digest::digest(&digest::SHA256, body));
+    let message = format!("{}\n{}\n{}\n{}\n{}", method, path, timestamp, account, body_hash);
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    util::hexstring(hmac::sign(&key, message.as_bytes()))
+}
+
+fn parse_basic(header: &str) -> Option<(String, String)> {
+    let mut parts = header.trim().splitn(2, ' ');
+    if !parts.next()?.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = base64::decode(parts.next()?.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut credentials = decoded.splitn(2, ':');
+    let username = credentials.next()?.to_owned();
+    let password = credentials.next()?.to_owned();
+    Some((username, password))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn basic_credentials() {
+        assert_eq!(
+            parse_basic("Basic R3JhbnRvdmljaDpzdGF0dWUgVFVSVExF"),
+            Some(("Grantovich".to_owned(), "statue TURTLE".to_owned()))
+        );
+        assert_eq!(parse_basic("Bearer R3JhbnRvdmljaDpzdGF0dWUgVFVSVExF"), None);
+        assert_eq!(parse_basic("Basic R3JhbnRvdmljaA=="), None);
+    }
+
+    #[test]
+    fn request_signature() {
+        assert_eq!(
+            sign("s3cr3t", "GET", "/accounts/1", "1600000000", "", b""),
+            "5971A20F82748B31A9A63663B2AD21BC0199480408E80C01AC6FE8CCBE000E57"
+        );
+        assert_ne!(
+            sign("s3cr3t", "GET", "/accounts/1", "1600000000", "2", b""),
+            sign("s3cr3t", "GET", "/accounts/1", "1600000000", "", b"")
+        );
+    }
+
+    #[test]
+    fn replayed_signature() {
+        let guard = ReplayGuard::default();
+        assert!(guard.check("A", 1000));
+        assert!(!guard.check("A", 1100));
+        assert!(guard.check("B", 1100));
+        assert!(guard.check("A", 1000 + 3 * SIGNATURE_WINDOW));
+    }
+
+    #[test]
+    fn permissions_by_gmlevel() {
+        let mut map = PermissionMap::new();
+        map.insert(1, vec![Permission::ViewHidden].into_iter().collect());
+        map.insert(3, vec![Permission::ManageAccounts].into_iter().collect());
+        assert!(permissions_for(&map, 0).is_empty());
+        assert_eq!(permissions_for(&map, 2).len(), 1);
+        assert!(permissions_for(&map, 3).contains(&Permission::ViewHidden));
+        assert!(permissions_for(&map, 3).contains(&Permission::ManageAccounts));
+    }
+
+    #[test]
+    fn least_privilege_defaults() {
+        let map = default_permissions();
+        assert!(permissions_for(&map, 0).is_empty());
+        assert!(!permissions_for(&map, 2).contains(&Permission::ManageAccounts));
+        assert!(!permissions_for(&map, 2).contains(&Permission::EditCampaign));
+        assert_eq!(permissions_for(&map, 3).len(), Permission::ALL.len());
+    }
+
+    #[test]
+    fn rbac_ids_over_defaults() {
+        let config: RbacConfig =
+            serde_yaml::from_str("realm: 1\nids: { view_accounts: 2000 }").unwrap();
+        assert_eq!(config.ids[&Permission::ViewAccounts], 2000);
+        assert_eq!(config.ids[&Permission::ManageAccounts], 1001);
+        assert_eq!(config.ids.len(), Permission::ALL.len());
+    }
+}
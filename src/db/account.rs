@@ -1,6 +1,38 @@
-use serde::Serialize;
-use sqlx::{mysql::MySqlPool, prelude::*};
-use crate::{error::AppResult, util};
+use serde::{Deserialize, Serialize};
+use sqlx::{mysql::{MySql, MySqlPool}, prelude::*};
+use crate::{error::{AppError, AppResult}, util};
+
+/// Longest username and password the core accepts at login.
+const USERNAME_MAX: usize = 16;
+const PASSWORD_MAX: usize = 16;
+const EMAIL_MAX: usize = 255;
+
+fn default_min_length() -> usize { 6 }
+fn default_forbid_username() -> bool { true }
+
+/// Strength rules for new passwords. Letter case does not count, the core
+/// hashes passwords upper cased.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordRules {
+    #[serde(default = "default_min_length")] pub min_length: usize,
+    #[serde(default)] pub require_letter: bool,
+    #[serde(default)] pub require_digit: bool,
+    #[serde(default)] pub require_symbol: bool,
+    #[serde(default = "default_forbid_username")] pub forbid_username: bool,
+}
+
+impl Default for PasswordRules {
+    fn default() -> Self {
+        Self {
+            min_length: default_min_length(),
+            require_letter: false,
+            require_digit: false,
+            require_symbol: false,
+            forbid_username: default_forbid_username(),
+        }
+    }
+}
 
 #[derive(Debug, Clone, FromRow, Serialize)]
 pub struct Account {
@@ -9,54 +41,386 @@ pub struct Account {
     pub gmlevel: Option<u8>,
 }
 
-pub async fn create(db: MySqlPool, username: &str, password: &str) -> AppResult<u32> {
+/// Access level granted on one realm, or on all of them for realm -1.
+#[derive(Debug, Clone, Serialize)]
+pub struct RealmAccess {
+    pub realm: i32,
+    pub gmlevel: u8,
+}
+
+/// The costlier parts of an account, read for the extended view.
+#[derive(Debug, Clone, Serialize)]
+pub struct Details {
+    pub joined_at: u32,
+    pub last_login: Option<u32>,
+    /// Only shown to the owner.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_ip: Option<String>,
+    pub locked: bool,
+    pub expansion: u8,
+    pub access: Vec<RealmAccess>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Email {
+    pub email: String,
+    pub verified: bool,
+}
+
+pub async fn create(db: MySqlPool, username: &str, password: &str, email: &str) -> AppResult<u32> {
+    insert(&db, username, password, email).await
+}
+
+pub(super) async fn insert<'c, E>(
+    executor: E,
+    username: &str,
+    password: &str,
+    email: &str,
+) -> AppResult<u32>
+where
+    E: Executor<'c, Database = MySql>,
+{
     let done = sqlx::query!(
-        "INSERT INTO account (username, sha_pass_hash) VALUES (?,?)",
+        "INSERT INTO account (username, sha_pass_hash, email, reg_mail) VALUES (?,?,?,?)",
         username.to_uppercase(),
-        make_password_hash(username, password))
-        .execute(&db)
+        make_password_hash(username, password),
+        email,
+        email)
+        .execute(executor)
         .await?;
     Ok(done.last_insert_id() as u32)
 }
 
-pub async fn replace(db: MySqlPool, id: u32, username: &str, password: &str) -> AppResult<()> {
+/// Creates or overwrites the account with `id`. An existing account keeps its
+/// address when none is given. Returns whether the address changed.
+pub async fn replace(
+    db: MySqlPool,
+    id: u32,
+    username: &str,
+    password: &str,
+    email: Option<&str>,
+) -> AppResult<bool> {
+    let mut tx = db.begin().await?;
+    let current = sqlx::query!("SELECT email FROM account WHERE id = ? FOR UPDATE", id)
+        .fetch_optional(&mut tx)
+        .await?
+        .map(|r| r.email);
+    let email = email.or_else(|| current.as_deref()).unwrap_or("").to_owned();
     sqlx::query!(
-        "REPLACE INTO account (id, username, sha_pass_hash) VALUES (?,?,?)",
+        "REPLACE INTO account (id, username, sha_pass_hash, email, reg_mail) VALUES (?,?,?,?,?)",
         id,
         username.to_uppercase(),
-        make_password_hash(username, password))
-        .execute(&db)
-        .await
-        .map(|_| ())
-        .map_err(From::from)
+        make_password_hash(username, password),
+        email,
+        email)
+        .execute(&mut tx)
+        .await?;
+    let changed = current.as_deref() != Some(email.as_str());
+    if changed {
+        clear_verified(&mut tx, id).await?;
+    }
+    tx.commit().await?;
+    Ok(changed)
 }
 
 pub async fn read(db: MySqlPool, id: u32) -> AppResult<Account> {
     sqlx::query_as!(
         Account,
-        "SELECT id, username, gmlevel \
+        "SELECT id, username, \
+         (SELECT MAX(gmlevel) FROM account_access WHERE account_access.id = account.id) AS gmlevel \
+         FROM account \
+         WHERE id = ?",
+        id)
+        .fetch_one(&db)
+        .await
+        .map_err(From::from)
+}
+
+pub async fn read_details(db: MySqlPool, id: u32) -> AppResult<Details> {
+    let access = sqlx::query!(
+        "SELECT RealmID AS realm, gmlevel FROM account_access WHERE id = ? ORDER BY RealmID",
+        id)
+        .fetch_all(&db)
+        .await?
+        .into_iter()
+        .map(|r| RealmAccess {
+            realm: r.realm,
+            gmlevel: r.gmlevel,
+        })
+        .collect();
+    sqlx::query!(
+        "SELECT UNIX_TIMESTAMP(joindate) AS joined_at, UNIX_TIMESTAMP(last_login) AS last_login, \
+         last_ip, locked, expansion \
          FROM account \
-         LEFT JOIN account_access USING (id) \
-         WHERE id = ? \
-         LIMIT 1",
+         WHERE id = ?",
         id)
         .fetch_one(&db)
         .await
+        .map(|r| Details {
+            joined_at: r.joined_at.unwrap_or(0) as u32,
+            // the core leaves a zero date until the first login
+            last_login: r.last_login.map(|t| t as u32).filter(|&t| t != 0),
+            last_ip: Some(r.last_ip),
+            locked: r.locked != 0,
+            expansion: r.expansion,
+            access,
+        })
         .map_err(From::from)
 }
 
-pub async fn update(db: MySqlPool, id: u32, username: &str, password: &str) -> AppResult<()> {
+/// The account's address and whether it was verified since it was last set.
+pub async fn read_email(db: MySqlPool, id: u32) -> AppResult<Email> {
     sqlx::query!(
-        "UPDATE account SET username = ?, sha_pass_hash = ? WHERE id = ?",
+        "SELECT account.email, v.email AS verified_email \
+         FROM account \
+         LEFT JOIN terra_verified_emails v ON v.account = account.id \
+         WHERE account.id = ?",
+        id)
+        .fetch_one(&db)
+        .await
+        .map(|r| Email {
+            verified: !r.email.is_empty() && r.verified_email.as_ref() == Some(&r.email),
+            email: r.email,
+        })
+        .map_err(From::from)
+}
+
+/// Marks an address verified, unless the account changed it after the
+/// verification was mailed.
+pub async fn verify_email(db: MySqlPool, id: u32, email: &str) -> AppResult<()> {
+    let done = sqlx::query!(
+        "INSERT INTO terra_verified_emails (account, email, verified_at) \
+         SELECT id, email, UNIX_TIMESTAMP() FROM account WHERE id = ? AND email = ? \
+         ON DUPLICATE KEY UPDATE email = VALUES(email), verified_at = VALUES(verified_at)",
         id,
-        username.to_uppercase(),
-        make_password_hash(username, password))
+        email)
         .execute(&db)
+        .await?;
+    if done.rows_affected() == 0 {
+        Err(AppError::Conflict)
+    } else {
+        Ok(())
+    }
+}
+
+/// Forgets the verification of an account's previous address.
+async fn clear_verified<'c, E>(executor: E, id: u32) -> AppResult<()>
+where
+    E: Executor<'c, Database = MySql>,
+{
+    sqlx::query!("DELETE FROM terra_verified_emails WHERE account = ?", id)
+        .execute(executor)
         .await
         .map(|_| ())
         .map_err(From::from)
 }
 
+/// Finds an account by username or address for a password reset. Only
+/// verified addresses are returned, and only for an address no other
+/// account has verified too.
+pub async fn find_verified(db: MySqlPool, login: &str) -> AppResult<Option<(u32, String)>> {
+    let by_username = sqlx::query!(
+        "SELECT account.id, account.email \
+         FROM account \
+         JOIN terra_verified_emails v ON v.account = account.id AND v.email = account.email \
+         WHERE account.username = ?",
+        login.to_uppercase())
+        .fetch_optional(&db)
+        .await?;
+    if let Some(r) = by_username {
+        return Ok(Some((r.id, r.email)));
+    }
+    // an address shared by several accounts doesn't tell which one is meant
+    let mut by_email = sqlx::query!(
+        "SELECT account.id, account.email \
+         FROM account \
+         JOIN terra_verified_emails v ON v.account = account.id AND v.email = account.email \
+         WHERE account.email = ? \
+         LIMIT 2",
+        login)
+        .fetch_all(&db)
+        .await?;
+    Ok(match by_email.len() {
+        1 => by_email.pop().map(|r| (r.id, r.email)),
+        _ => None,
+    })
+}
+
+/// Highest gmlevel granted to an account on `realm` or on all realms.
+pub async fn gmlevel(db: MySqlPool, id: u32, realm: i32) -> AppResult<u8> {
+    sqlx::query!(
+        "SELECT MAX(gmlevel) AS gmlevel FROM account_access \
+         WHERE id = ? AND (RealmID = ? OR RealmID = -1)",
+        id,
+        realm)
+        .fetch_one(&db)
+        .await
+        .map(|r| r.gmlevel.unwrap_or(0))
+        .map_err(From::from)
+}
+
+/// Finds the account matching login credentials. Its gmlevel is the highest
+/// one granted on any realm, permissions go by the realm's one.
+pub async fn authenticate(db: MySqlPool, username: &str, password: &str) -> AppResult<Option<Account>> {
+    let row = sqlx::query!(
+        "SELECT id, username, sha_pass_hash, \
+         (SELECT MAX(gmlevel) FROM account_access WHERE account_access.id = account.id) AS gmlevel \
+         FROM account \
+         WHERE username = ?",
+        username.to_uppercase())
+        .fetch_optional(&db)
+        .await?;
+    let expected = make_password_hash(username, password);
+    Ok(row
+        .filter(|r| {
+            ring::constant_time::verify_slices_are_equal(
+                r.sha_pass_hash.to_uppercase().as_bytes(),
+                expected.as_bytes(),
+            )
+            .is_ok()
+        })
+        .map(|r| Account {
+            id: r.id,
+            username: r.username,
+            gmlevel: r.gmlevel,
+        }))
+}
+
+/// Fields of an account to change, absent ones stay as they are.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Patch {
+    #[serde(default)] pub username: Option<String>,
+    #[serde(default)] pub password: Option<String>,
+    #[serde(default)] pub email: Option<String>,
+    #[serde(default)] pub locked: Option<bool>,
+    #[serde(default)] pub lock_country: Option<String>,
+}
+
+/// Applies a patch and returns the fields whose value actually changed. The
+/// password hash includes the username, so a new username needs the password.
+pub async fn update(db: MySqlPool, id: u32, patch: &Patch) -> AppResult<Vec<&'static str>> {
+    if patch.username.is_some() && patch.password.is_none() {
+        return Err(AppError::InvalidInput("password_required"));
+    }
+    let mut tx = db.begin().await?;
+    let current = sqlx::query!(
+        "SELECT username, sha_pass_hash, email, locked, lock_country \
+         FROM account WHERE id = ? FOR UPDATE",
+        id)
+        .fetch_one(&mut tx)
+        .await?;
+    let mut changed = Vec::new();
+
+    if let Some(password) = &patch.password {
+        let username = patch.username.as_deref().unwrap_or(&current.username);
+        if username.to_uppercase() != current.username.to_uppercase() {
+            changed.push("username");
+        }
+        if make_password_hash(&current.username, password) != current.sha_pass_hash.to_uppercase() {
+            changed.push("password");
+        }
+        if !changed.is_empty() {
+            // the core derives its SRP6 values from the hash again once they are cleared
+            sqlx::query!(
+                "UPDATE account SET username = ?, sha_pass_hash = ?, v = '', s = '' WHERE id = ?",
+                username.to_uppercase(),
+                make_password_hash(username, password),
+                id)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+    if let Some(email) = patch.email.as_deref().filter(|&e| e != current.email) {
+        sqlx::query!("UPDATE account SET email = ? WHERE id = ?", email, id)
+            .execute(&mut tx)
+            .await?;
+        clear_verified(&mut tx, id).await?;
+        changed.push("email");
+    }
+    if let Some(locked) = patch.locked.filter(|&l| l != (current.locked != 0)) {
+        sqlx::query!("UPDATE account SET locked = ? WHERE id = ?", locked, id)
+            .execute(&mut tx)
+            .await?;
+        changed.push("locked");
+    }
+    if let Some(country) = patch.lock_country.as_deref().filter(|&c| c != current.lock_country) {
+        sqlx::query!("UPDATE account SET lock_country = ? WHERE id = ?", country, id)
+            .execute(&mut tx)
+            .await?;
+        changed.push("lock_country");
+    }
+
+    tx.commit().await?;
+    Ok(changed)
+}
+
+/// Checks a username against what the core and the client accept. Only
+/// ASCII letters and digits upper case the same way on both ends.
+pub fn check_username(username: &str) -> AppResult<()> {
+    if username.is_empty() || username.len() > USERNAME_MAX {
+        Err(AppError::InvalidInput("username_length"))
+    } else if !username.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Err(AppError::InvalidInput("username_charset"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks an address loosely, an empty one clears it.
+pub fn check_email(email: &str) -> AppResult<()> {
+    let mut parts = email.splitn(2, '@');
+    let valid = match (parts.next(), parts.next()) {
+        _ if email.is_empty() => true,
+        (Some(local), Some(domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+        }
+        _ => false,
+    };
+    if valid && email.len() <= EMAIL_MAX && !email.chars().any(char::is_whitespace) {
+        Ok(())
+    } else {
+        Err(AppError::InvalidInput("email"))
+    }
+}
+
+/// Country the account is locked to, `00` for none.
+pub fn check_lock_country(country: &str) -> AppResult<()> {
+    if country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase() || c == '0') {
+        Ok(())
+    } else {
+        Err(AppError::InvalidInput("lock_country"))
+    }
+}
+
+/// Checks a password against the core limits and the configured rules.
+pub fn check_password(username: &str, password: &str, rules: &PasswordRules) -> AppResult<()> {
+    let has = |test: fn(&char) -> bool| password.chars().any(|c| test(&c));
+    if password.is_empty() || password.len() > PASSWORD_MAX {
+        Err(AppError::InvalidInput("password_length"))
+    } else if !password.chars().all(|c| c.is_ascii_graphic() || c == ' ') {
+        Err(AppError::InvalidInput("password_charset"))
+    } else if password.len() < rules.min_length {
+        Err(AppError::InvalidInput("password_too_short"))
+    } else if rules.require_letter && !has(char::is_ascii_alphabetic) {
+        Err(AppError::InvalidInput("password_needs_letter"))
+    } else if rules.require_digit && !has(char::is_ascii_digit) {
+        Err(AppError::InvalidInput("password_needs_digit"))
+    } else if rules.require_symbol && !has(char::is_ascii_punctuation) {
+        Err(AppError::InvalidInput("password_needs_symbol"))
+    } else if rules.forbid_username
+        && password.to_uppercase().contains(&username.to_uppercase())
+    {
+        Err(AppError::InvalidInput("password_contains_username"))
+    } else {
+        Ok(())
+    }
+}
+
 // pub async fn delete(db: MySqlPool, id: u32) -> AppResult<()> {
 //     let mut tx = db.begin().await?;
 //     sqlx::query!("DELETE FROM account WHERE id = ?", id).execute(&mut tx).await?;
@@ -78,4 +442,33 @@ mod test {
         assert_eq!(make_password_hash("Grantovich", "statue TURTLE"), "6834AEE863135609C53AEC2DF41D65D1E6E24811");
         assert_eq!(make_password_hash("Stif", "snakinglying34"), "9008FF7AB85467ECF9F067C76D4FACD619077793");
     }
+
+    #[test]
+    fn credential_rules() {
+        assert!(check_username("Grantovich").is_ok());
+        assert!(check_username("").is_err());
+        assert!(check_username("Grantovich1234567").is_err());
+        assert!(check_username("Grant:ovich").is_err());
+        assert!(check_username("Grantövich").is_err());
+
+        let rules = PasswordRules {
+            require_digit: true,
+            ..PasswordRules::default()
+        };
+        assert!(check_password("Stif", "snakinglying34", &rules).is_ok());
+        assert!(check_password("Stif", "snakinglying", &rules).is_err());
+        assert!(check_password("Stif", "snak34", &rules).is_ok());
+        assert!(check_password("Stif", "snk34", &rules).is_err());
+        assert!(check_password("Stif", "snakinglying3456", &rules).is_ok());
+        assert!(check_password("Stif", "snakinglying34567", &rules).is_err());
+        assert!(check_password("Stif", "sTIFf1234", &rules).is_err());
+        assert!(check_password("Stif", "snaking\tlying34", &rules).is_err());
+
+        assert!(check_email("stif@example.org").is_ok());
+        assert!(check_email("").is_ok());
+        assert!(check_email("stif").is_err());
+        assert!(check_email("stif@example").is_err());
+        assert!(check_email("st if@example.org").is_err());
+        assert!(check_email("stif@exa@mple.org").is_err());
+    }
 }
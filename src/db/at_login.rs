@@ -0,0 +1,80 @@
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{mysql::MySqlPool, prelude::*};
+use crate::error::AppResult;
+use super::{audit, character::AtLoginFlags};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Updated,
+    /// The core saves its own copy of the flags on logout, so online characters are left alone.
+    Online,
+    NotFound,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Outcome {
+    pub guid: u32,
+    pub status: Status,
+    pub at_login: Vec<&'static str>,
+}
+
+/// Sets and clears at-login flags on each character, recording every change.
+pub async fn update(
+    db: MySqlPool,
+    gm: u32,
+    guids: &[u32],
+    set: AtLoginFlags,
+    clear: AtLoginFlags,
+) -> AppResult<Vec<Outcome>> {
+    let mut outcomes = Vec::with_capacity(guids.len());
+    for guid in guids.iter().cloned() {
+        let mut tx = db.begin().await?;
+        let character = sqlx::query!(
+            "SELECT online, at_login FROM characters WHERE guid = ? FOR UPDATE",
+            guid)
+            .fetch_optional(&mut tx)
+            .await?;
+
+        let outcome = match character {
+            None => Outcome {
+                guid,
+                status: Status::NotFound,
+                at_login: Vec::new(),
+            },
+            Some(c) => {
+                let before = AtLoginFlags::from_bits_truncate(c.at_login as u16);
+                if c.online != 0 {
+                    Outcome {
+                        guid,
+                        status: Status::Online,
+                        at_login: before.names(),
+                    }
+                } else {
+                    let after = (before | set) - clear;
+                    sqlx::query!(
+                        "UPDATE characters SET at_login = ? WHERE guid = ?",
+                        after.bits,
+                        guid)
+                        .execute(&mut tx)
+                        .await?;
+                    audit::record(&mut tx, gm, "at_login", Some(guid), json!({
+                        "before": before.names(),
+                        "after": after.names(),
+                    }))
+                    .await?;
+                    Outcome {
+                        guid,
+                        status: Status::Updated,
+                        at_login: after.names(),
+                    }
+                }
+            }
+        };
+
+        tx.commit().await?;
+        outcomes.push(outcome);
+    }
+    Ok(outcomes)
+}
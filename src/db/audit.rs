@@ -0,0 +1,62 @@
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sqlx::{mysql::{MySql, MySqlPool}, prelude::*};
+use crate::error::AppResult;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Entry {
+    pub id: u32,
+    pub account: u32,
+    pub action: String,
+    pub guid: Option<u32>,
+    pub details: JsonValue,
+    pub created_at: u32,
+}
+
+/// Records a staff action, usually within the transaction that performs it.
+pub async fn record<'c, E>(
+    executor: E,
+    account: u32,
+    action: &str,
+    guid: Option<u32>,
+    details: JsonValue,
+) -> AppResult<()>
+where
+    E: Executor<'c, Database = MySql>,
+{
+    sqlx::query!(
+        "INSERT INTO terra_audit_log (account, action, guid, details, created_at) \
+         VALUES (?,?,?,?,UNIX_TIMESTAMP())",
+        account,
+        action,
+        guid,
+        details)
+        .execute(executor)
+        .await
+        .map(|_| ())
+        .map_err(From::from)
+}
+
+pub async fn list_character(db: MySqlPool, guid: u32) -> AppResult<Vec<Entry>> {
+    sqlx::query!(
+        "SELECT id, account, action, guid, details, created_at \
+         FROM terra_audit_log \
+         WHERE guid = ? \
+         ORDER BY id DESC",
+        guid)
+        .fetch_all(&db)
+        .await
+        .map(|v| {
+            v.into_iter()
+                .map(|e| Entry {
+                    id: e.id,
+                    account: e.account,
+                    action: e.action,
+                    guid: e.guid,
+                    details: e.details.unwrap_or(JsonValue::Null),
+                    created_at: e.created_at,
+                })
+                .collect()
+        })
+        .map_err(From::from)
+}
@@ -0,0 +1,103 @@
+use serde::Serialize;
+use sqlx::{mysql::MySqlPool, prelude::*};
+use crate::error::{AppError, AppResult};
+
+/// A row of the core's `account_banned`, where a permanent ban ends when it starts.
+#[derive(Debug, Clone, Serialize)]
+pub struct Ban {
+    pub banned_at: u32,
+    /// None for permanent bans.
+    pub expires_at: Option<u32>,
+    pub banned_by: String,
+    pub reason: String,
+    /// Still in force: neither lifted nor expired.
+    pub active: bool,
+}
+
+/// Bans an account for `duration` seconds, or for good. Bans already in force
+/// are lifted so the new one replaces them, one from the same second is
+/// overwritten as the table is keyed by account and ban date.
+pub async fn ban(
+    db: MySqlPool,
+    account: u32,
+    banned_by: &str,
+    duration: Option<u32>,
+    reason: &str,
+) -> AppResult<Ban> {
+    let mut tx = db.begin().await?;
+    sqlx::query!("SELECT id FROM account WHERE id = ? FOR UPDATE", account)
+        .fetch_one(&mut tx)
+        .await?;
+    sqlx::query!(
+        "UPDATE account_banned SET active = 0 WHERE id = ? AND active = 1",
+        account)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO account_banned (id, bandate, unbandate, bannedby, banreason, active) \
+         VALUES (?,UNIX_TIMESTAMP(),UNIX_TIMESTAMP() + ?,?,?,1) \
+         ON DUPLICATE KEY UPDATE \
+         unbandate = VALUES(unbandate), \
+         bannedby = VALUES(bannedby), \
+         banreason = VALUES(banreason), \
+         active = 1",
+        account,
+        duration.unwrap_or(0),
+        banned_by,
+        reason)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    active(db, account).await?.ok_or(AppError::NotFound)
+}
+
+/// Lifts the bans in force, keeping them in the history.
+pub async fn unban(db: MySqlPool, account: u32) -> AppResult<()> {
+    let done = sqlx::query!(
+        "UPDATE account_banned SET active = 0 WHERE id = ? AND active = 1",
+        account)
+        .execute(&db)
+        .await?;
+    if done.rows_affected() == 0 {
+        Err(AppError::NotFound)
+    } else {
+        Ok(())
+    }
+}
+
+/// All bans of an account, newest first.
+pub async fn history(db: MySqlPool, account: u32) -> AppResult<Vec<Ban>> {
+    sqlx::query!(
+        "SELECT bandate, unbandate, bannedby, banreason, \
+         (active = 1 AND (unbandate = bandate OR unbandate > UNIX_TIMESTAMP())) AS in_force \
+         FROM account_banned \
+         WHERE id = ? \
+         ORDER BY bandate DESC",
+        account)
+        .fetch_all(&db)
+        .await
+        .map(|v| {
+            v.into_iter()
+                .map(|r| Ban {
+                    banned_at: r.bandate,
+                    expires_at: Some(r.unbandate).filter(|&until| until != r.bandate),
+                    banned_by: r.bannedby,
+                    reason: r.banreason,
+                    active: r.in_force == Some(1),
+                })
+                .collect()
+        })
+        .map_err(From::from)
+}
+
+pub async fn active(db: MySqlPool, account: u32) -> AppResult<Option<Ban>> {
+    Ok(history(db, account).await?.into_iter().find(|ban| ban.active))
+}
+
+/// Fails with `AppError::Banned` while a ban is in force.
+pub async fn check(db: MySqlPool, account: u32) -> AppResult<()> {
+    match active(db, account).await? {
+        Some(ban) => Err(AppError::Banned(ban.expires_at)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,262 @@
+use serde::Serialize;
+use sqlx::{mysql::{MySql, MySqlPool}, prelude::*, Transaction};
+use crate::{
+    db::Backend,
+    error::{AppError, AppResult},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Revision {
+    pub id: u32,
+    pub author: u32,
+    pub info: Option<String>,
+    pub name_extra: Option<String>,
+    pub approved: bool,
+    pub approved_by: Option<u32>,
+    /// A later revision was made while this one was pending, it can't be approved.
+    pub superseded: bool,
+    pub created_at: u32,
+}
+
+/// Current state of an editable character.
+pub struct Current {
+    pub account: u32,
+    pub locked: bool,
+    pub info: Option<String>,
+    pub name_extra: Option<String>,
+}
+
+pub async fn current(db: MySqlPool, guid: u32) -> AppResult<Current> {
+    read_current(&db, guid).await
+}
+
+/// Saves a new biography and name extra. Unless `pending` is set they are
+/// applied right away, otherwise the revision waits for a GM to approve it.
+pub async fn edit(
+    db: MySqlPool,
+    backend: Backend,
+    guid: u32,
+    author: u32,
+    info: Option<String>,
+    name_extra: Option<String>,
+    pending: bool,
+) -> AppResult<Revision> {
+    let mut tx = db.begin().await?;
+    let current = read_current(&mut tx, guid).await?;
+    save_original(&mut tx, guid, &current).await?;
+    if !pending {
+        apply(&mut tx, backend, guid, info.as_deref(), name_extra.as_deref()).await?;
+    }
+    let id = insert(&mut tx, guid, author, &info, &name_extra, !pending, None).await?;
+    let revision = read(&mut tx, guid, id).await?;
+    tx.commit().await?;
+    Ok(revision)
+}
+
+pub async fn list(db: MySqlPool, guid: u32) -> AppResult<Vec<Revision>> {
+    sqlx::query!(
+        "SELECT id, author, info, name_extra, approved, approved_by, superseded, created_at \
+         FROM terra_character_revisions \
+         WHERE guid = ? \
+         ORDER BY id DESC",
+        guid)
+        .fetch_all(&db)
+        .await
+        .map(|v| {
+            v.into_iter()
+                .map(|r| Revision {
+                    id: r.id,
+                    author: r.author,
+                    info: r.info,
+                    name_extra: r.name_extra,
+                    approved: r.approved != 0,
+                    approved_by: r.approved_by,
+                    superseded: r.superseded != 0,
+                    created_at: r.created_at,
+                })
+                .collect()
+        })
+        .map_err(From::from)
+}
+
+/// Applies a pending revision, unless a later one was made or applied since.
+pub async fn approve(
+    db: MySqlPool,
+    backend: Backend,
+    guid: u32,
+    revision: u32,
+    gm: u32,
+) -> AppResult<Revision> {
+    let mut tx = db.begin().await?;
+    // locks the character against edits made meanwhile
+    read_current(&mut tx, guid).await?;
+    let target = read(&mut tx, guid, revision).await?;
+    let latest = sqlx::query!(
+        "SELECT MAX(id) AS id FROM terra_character_revisions WHERE guid = ? AND approved = 1",
+        guid)
+        .fetch_one(&mut tx)
+        .await?
+        .id;
+    if target.approved || target.superseded || latest.map_or(false, |id| id > target.id) {
+        return Err(AppError::Conflict);
+    }
+    apply(&mut tx, backend, guid, target.info.as_deref(), target.name_extra.as_deref()).await?;
+    sqlx::query!(
+        "UPDATE terra_character_revisions SET approved = 1, approved_by = ? WHERE id = ?",
+        gm,
+        revision)
+        .execute(&mut tx)
+        .await?;
+    let revision = read(&mut tx, guid, revision).await?;
+    tx.commit().await?;
+    Ok(revision)
+}
+
+/// Brings a character back to the state of an earlier revision, recorded as a new one.
+pub async fn revert(
+    db: MySqlPool,
+    backend: Backend,
+    guid: u32,
+    revision: u32,
+    gm: u32,
+) -> AppResult<Revision> {
+    let mut tx = db.begin().await?;
+    let current = read_current(&mut tx, guid).await?;
+    save_original(&mut tx, guid, &current).await?;
+    let target = read(&mut tx, guid, revision).await?;
+    apply(&mut tx, backend, guid, target.info.as_deref(), target.name_extra.as_deref()).await?;
+    let id = insert(&mut tx, guid, gm, &target.info, &target.name_extra, true, Some(gm)).await?;
+    let revision = read(&mut tx, guid, id).await?;
+    tx.commit().await?;
+    Ok(revision)
+}
+
+async fn read_current<'c, E>(executor: E, guid: u32) -> AppResult<Current>
+where
+    E: Executor<'c, Database = MySql>,
+{
+    let row = sqlx::query!(
+        "SELECT account, COALESCE(t.locked, 0) AS locked, \
+         JSON_UNQUOTE(JSON_EXTRACT(t.metadata, '$.info')) AS info, \
+         t.name_extra \
+         FROM characters \
+         LEFT JOIN terra_character_data t USING (guid) \
+         WHERE guid = ? FOR UPDATE",
+        guid)
+        .fetch_one(executor)
+        .await?;
+    Ok(Current {
+        account: row.account,
+        locked: row.locked != 0,
+        // JSON null unquotes into the string "null"
+        info: row.info.filter(|s| s != "null"),
+        name_extra: row.name_extra,
+    })
+}
+
+async fn read(tx: &mut Transaction<'_, MySql>, guid: u32, id: u32) -> AppResult<Revision> {
+    sqlx::query!(
+        "SELECT id, author, info, name_extra, approved, approved_by, superseded, created_at \
+         FROM terra_character_revisions \
+         WHERE id = ? AND guid = ?",
+        id,
+        guid)
+        .fetch_one(&mut *tx)
+        .await
+        .map(|r| Revision {
+            id: r.id,
+            author: r.author,
+            info: r.info,
+            name_extra: r.name_extra,
+            approved: r.approved != 0,
+            approved_by: r.approved_by,
+            superseded: r.superseded != 0,
+            created_at: r.created_at,
+        })
+        .map_err(From::from)
+}
+
+/// Characters created before history existed get their original state saved first.
+async fn save_original(
+    tx: &mut Transaction<'_, MySql>,
+    guid: u32,
+    current: &Current,
+) -> AppResult<()> {
+    let known = sqlx::query!(
+        "SELECT COUNT(*) AS num FROM terra_character_revisions WHERE guid = ?",
+        guid)
+        .fetch_one(&mut *tx)
+        .await?
+        .num;
+    if known == 0 {
+        insert(tx, guid, current.account, &current.info, &current.name_extra, true, None).await?;
+    }
+    Ok(())
+}
+
+async fn insert(
+    tx: &mut Transaction<'_, MySql>,
+    guid: u32,
+    author: u32,
+    info: &Option<String>,
+    name_extra: &Option<String>,
+    approved: bool,
+    approved_by: Option<u32>,
+) -> AppResult<u32> {
+    // only the newest revision may still be approved
+    sqlx::query!(
+        "UPDATE terra_character_revisions SET superseded = 1 \
+         WHERE guid = ? AND approved = 0 AND superseded = 0",
+        guid)
+        .execute(&mut *tx)
+        .await?;
+    let done = sqlx::query!(
+        "INSERT INTO terra_character_revisions \
+         (guid, author, info, name_extra, approved, approved_by, created_at) \
+         VALUES (?,?,?,?,?,?,UNIX_TIMESTAMP())",
+        guid,
+        author,
+        info,
+        name_extra,
+        approved,
+        approved_by)
+        .execute(&mut *tx)
+        .await?;
+    Ok(done.last_insert_id() as u32)
+}
+
+/// Skyland's view always has the character's row, stock cores' side table
+/// only has it for characters terra created or edited before.
+async fn apply(
+    tx: &mut Transaction<'_, MySql>,
+    backend: Backend,
+    guid: u32,
+    info: Option<&str>,
+    name_extra: Option<&str>,
+) -> AppResult<()> {
+    let done = match backend {
+        Backend::Skyland => sqlx::query!(
+            "UPDATE terra_character_data SET \
+             name_extra = ?, \
+             metadata = JSON_SET(COALESCE(metadata, JSON_OBJECT()), '$.info', ?) \
+             WHERE guid = ?",
+            name_extra,
+            info,
+            guid)
+            .execute(&mut *tx)
+            .await,
+        Backend::Trinity => sqlx::query!(
+            "INSERT INTO terra_character_data (guid, name_extra, metadata) \
+             VALUES (?,?,JSON_OBJECT('info', ?)) \
+             ON DUPLICATE KEY UPDATE \
+             name_extra = VALUES(name_extra), \
+             metadata = JSON_SET(COALESCE(metadata, JSON_OBJECT()), '$.info', ?)",
+            guid,
+            name_extra,
+            info,
+            info)
+            .execute(&mut *tx)
+            .await,
+    };
+    done.map(|_| ()).map_err(From::from)
+}
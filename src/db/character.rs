@@ -9,13 +9,21 @@ use once_cell::sync::Lazy;
 use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
-use sqlx::{mysql::MySqlPool, prelude::*};
+use sqlx::{
+    mysql::{MySql, MySqlArguments, MySqlPool},
+    prelude::*,
+    query::QueryAs,
+    Transaction,
+};
 use crate::{
     util,
+    db::Backend,
+    dbc::ClientData,
     error::{AppError, AppResult},
     framework::{
         campaign::{Campaign, RoleKind},
-        system::{Armor, Mods, Weapon},
+        catalog::{Catalog, Contents},
+        system::{Appearance, Armor, Homebind, Mods, Race, Weapon},
         tags::Tags,
     }
 };
@@ -23,7 +31,19 @@ use crate::{
 const LEVEL_MIN: i32 = 1;
 const LEVEL_MAX: i32 = 80;
 const EQUIP_SLOTS: usize = 23;
+const KNOWN_TITLES_SIZE: usize = 6;
+const TAXI_MASK_SIZE: usize = 14;
+const FACTION_FLAG_VISIBLE: u8 = 0x01;
+const INVENTORY_SLOT_ITEM_START: usize = 23;
+const INVENTORY_SLOT_ITEM_END: usize = 39;
+const LIST_LIMIT_DEFAULT: u32 = 50;
+const LIST_LIMIT_MAX: u32 = 200;
 type EquipArray = [Option<NonZeroU32>; EQUIP_SLOTS];
+const EQUIP_SLOT_NAMES: [&str; EQUIP_SLOTS] = [
+    "head", "neck", "shoulders", "body", "chest", "waist", "legs", "feet", "wrists", "hands",
+    "finger", "finger", "trinket", "trinket", "back", "mainhand", "offhand", "ranged", "tabard",
+    "bag", "bag", "bag", "bag",
+];
 
 static NAME_REGEX: Lazy<Regex> = Lazy::new(|| {
     RegexBuilder::new(r"[а-я]{2,12}")
@@ -52,15 +72,49 @@ bitflags! {
     }
 }
 
+const AT_LOGIN_NAMES: [(&str, AtLoginFlags); 9] = [
+    ("rename", AtLoginFlags::RENAME),
+    ("reset_spells", AtLoginFlags::RESET_SPELLS),
+    ("reset_talents", AtLoginFlags::RESET_TALENTS),
+    ("customize", AtLoginFlags::CUSTOMIZE),
+    ("reset_pet_talents", AtLoginFlags::RESET_PET_TALENTS),
+    ("first_login", AtLoginFlags::FIRST_LOGIN),
+    ("change_faction", AtLoginFlags::CHANGE_FACTION),
+    ("change_race", AtLoginFlags::CHANGE_RACE),
+    ("resurrect", AtLoginFlags::RESURRECT),
+];
+
+impl AtLoginFlags {
+    /// Parses snake_case flag names, `None` if any of them is unknown.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let mut flags = Self::empty();
+        for name in names {
+            let (_, flag) = AT_LOGIN_NAMES.iter().find(|(n, _)| *n == name)?;
+            flags |= *flag;
+        }
+        Some(flags)
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        AT_LOGIN_NAMES
+            .iter()
+            .filter(|(_, flag)| self.contains(*flag))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
 #[derive(Serialize, Deserialize, FromRow)]
 #[serde(deny_unknown_fields)]
 pub struct Data {
     pub guid: u32,
+    #[serde(skip)] pub account: u32,
     pub name: Option<String>,
     pub name_extra: Option<String>,
     pub female: bool,
-    pub race: u8,
-    pub class: u8,
+    /// Game ids, left out when the audience may not see the campaign's race or class.
+    #[serde(skip_serializing_if = "Option::is_none")] pub race: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub class: Option<u8>,
     pub level: u8,
     pub locked: bool,
     pub stashed: bool,
@@ -68,6 +122,28 @@ pub struct Data {
     pub metadata: JsonValue,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListFilter {
+    #[serde(default)] pub cursor: Option<u32>,
+    #[serde(default)] pub limit: Option<u32>,
+    #[serde(default)] pub name: Option<String>,
+    #[serde(default)] pub race: Option<String>,
+    #[serde(default)] pub class: Option<String>,
+    #[serde(default)] pub role: Option<String>,
+    #[serde(default)] pub block: Option<String>,
+    #[serde(default)] pub locked: Option<bool>,
+    #[serde(default)] pub stashed: Option<bool>,
+    #[serde(default)] pub online: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub next: Option<u32>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Form {
@@ -82,6 +158,7 @@ pub struct Form {
     #[serde(default)] pub weapon: Option<String>,
     #[serde(default)] pub traits: HashSet<String>,
     pub location: String,
+    #[serde(default)] pub appearance: Option<Appearance>,
 }
 
 pub struct CreationData {
@@ -91,6 +168,7 @@ pub struct CreationData {
     pub female: bool,
     pub race: u8,
     pub class: u8,
+    pub appearance: Option<Appearance>,
     pub level: u8,
     pub map: u32,
     pub zone: u32,
@@ -102,6 +180,12 @@ pub struct CreationData {
     pub starting_equip: Option<EquipArray>,
     pub starting_items: HashMap<NonZeroU32, NonZeroU32>,
     pub money: u32,
+    pub reputations: HashMap<NonZeroU32, i32>,
+    /// Bits of `knownTitles`, not title ids.
+    pub known_titles: HashSet<u32>,
+    pub rewarded_quests: HashSet<NonZeroU32>,
+    pub taxi_nodes: HashSet<NonZeroU32>,
+    pub homebind: Option<Homebind>,
     pub metadata: JsonValue,
 }
 
@@ -115,10 +199,37 @@ pub struct Metadata {
     #[serde(default)] pub weapon: Option<String>,
     #[serde(default)] pub traits: Option<HashSet<String>>,
     #[serde(default)] pub location: Option<String>,
+    #[serde(default)] pub campaign_version: Option<String>,
+}
+
+impl ListFilter {
+    /// Resolves role and block filters into the set of role ids to match.
+    pub fn roles(&self, campaign: &Campaign) -> AppResult<Option<Vec<String>>> {
+        let block_roles = if let Some(id) = &self.block {
+            let block = campaign
+                .blocks
+                .iter()
+                .find(|b| &b.id == id)
+                .ok_or(AppError::InvalidInput("block"))?;
+            Some(&block.roles)
+        } else {
+            None
+        };
+        Ok(match (&self.role, block_roles) {
+            (Some(role), Some(roles)) => Some(roles.iter().filter(|r| *r == role).cloned().collect()),
+            (Some(role), None) => Some(vec![role.clone()]),
+            (None, Some(roles)) => Some(roles.clone()),
+            (None, None) => None,
+        })
+    }
 }
 
 impl Form {
-    pub fn into_cdata(self, campaign: &Campaign) -> AppResult<CreationData> {
+    pub fn into_cdata(
+        self,
+        campaign: &Campaign,
+        client: Option<&ClientData>,
+    ) -> AppResult<CreationData> {
         fn ensure(reason: &'static str, success: bool) -> AppResult<()> {
             if success {
                 Ok(())
@@ -127,11 +238,8 @@ impl Form {
             }
         }
 
-        let name = util::prepare_name(&self.name);
-        let name_extra = util::prepare_name_extra(self.name_extra.as_deref());
-
-        ensure("name", NAME_REGEX.is_match(&name))?;
-        if let Some(s) = &name_extra { ensure("name_extra", NAME_EXTRA_REGEX.is_match(s))? }
+        let name = prepare_name(&self.name)?;
+        let name_extra = check_name_extra(self.name_extra.as_deref())?;
 
         // fetch entity definitions
         let role = campaign
@@ -164,6 +272,10 @@ impl Form {
         } else {
             None
         };
+        if let Some(client) = client {
+            ensure("race/class", client.can_create(race.game_id, class.game_id))?;
+        }
+
         let traits = fall_iter(self.traits.iter().map(|id| {
             campaign.system.traits.get(id).ok_or(AppError::InvalidInput("traits"))
         })).collect::<Vec<_>>()?;
@@ -225,6 +337,12 @@ impl Form {
             mods.merge_in(&t.mods)
         }
 
+        // a preset from the campaign takes precedence over the player's choice
+        let appearance = mods.appearance.or(self.appearance);
+        if let Some(a) = &appearance {
+            ensure("appearance", appearance_allowed(a, race, self.female, client))?;
+        }
+
         Ok(CreationData {
             locked: role.kind != RoleKind::Free,
             name,
@@ -232,6 +350,7 @@ impl Form {
             female: self.female,
             race: race.game_id,
             class: class.game_id,
+            appearance,
             level: min(LEVEL_MAX, 1 + max(LEVEL_MIN, mods.level)) as u8,
             map: location.map,
             zone: location.zone,
@@ -243,6 +362,11 @@ impl Form {
             starting_equip: Some(make_equip_array(armor, weapon)),
             starting_items: make_pairs_map(&mods.items),
             money: max(0, mods.money) as u32,
+            reputations: mods.reputations,
+            known_titles: make_title_bits(&mods.titles, client)?,
+            rewarded_quests: mods.quests,
+            taxi_nodes: mods.taxi_nodes,
+            homebind: mods.homebind,
             metadata: json!({
                 "info": self.info,
                 "role": self.role,
@@ -252,12 +376,76 @@ impl Form {
                 "weapon": self.weapon,
                 "traits": self.traits,
                 "location": self.location,
+                "campaign_version": campaign.version,
             }),
         })
     }
 }
 
-pub async fn create(db: MySqlPool, account: u32, data: CreationData) -> AppResult<u32> {
+impl CreationData {
+    fn at_login(&self) -> AtLoginFlags {
+        if self.appearance.is_some() {
+            AtLoginFlags::FIRST_LOGIN
+        } else {
+            AtLoginFlags::FIRST_LOGIN | AtLoginFlags::CUSTOMIZE
+        }
+    }
+
+    pub fn contents(&self, catalog: &Catalog) -> Contents {
+        let mods = Mods {
+            spells: self.innate_spells.clone(),
+            skills: self
+                .starting_skills
+                .iter()
+                .map(|(id, value)| (*id, value.get() as i32))
+                .collect(),
+            items: self
+                .starting_items
+                .iter()
+                .map(|(id, count)| (*id, count.get() as i32))
+                .collect(),
+            ..Mods::new()
+        };
+        let equipment = self
+            .starting_equip
+            .iter()
+            .flat_map(|equip| EQUIP_SLOT_NAMES.iter().zip(equip.iter()))
+            .filter_map(|(slot, maybe_id)| maybe_id.map(|id| (*slot, id)));
+        catalog.contents(&mods, equipment)
+    }
+}
+
+pub async fn create(
+    db: MySqlPool,
+    backend: Backend,
+    catalog: &Catalog,
+    account: u32,
+    data: CreationData,
+) -> AppResult<u32> {
+    let mut tx = db.begin().await?;
+    let reserved = sqlx::query!(
+        "SELECT COUNT(*) AS num FROM terra_name_reservations WHERE name = ?",
+        data.name)
+        .fetch_one(&mut tx)
+        .await?
+        .num;
+    if reserved != 0 {
+        return Err(AppError::Conflict);
+    }
+    let guid = match backend {
+        Backend::Skyland => insert_skyland(&mut tx, account, &data).await?,
+        Backend::Trinity => insert_trinity(&mut tx, catalog, account, &data).await?,
+    };
+    insert_progress(&mut tx, guid, &data).await?;
+    tx.commit().await?;
+    Ok(guid)
+}
+
+async fn insert_skyland(
+    tx: &mut Transaction<'_, MySql>,
+    account: u32,
+    data: &CreationData,
+) -> AppResult<u32> {
     let done = sqlx::query!(
         "INSERT INTO characters (\
          account, \
@@ -268,6 +456,11 @@ pub async fn create(db: MySqlPool, account: u32, data: CreationData) -> AppResul
          gender, \
          race, \
          class, \
+         skin, \
+         face, \
+         hairStyle, \
+         hairColor, \
+         facialStyle, \
          level, \
          map, \
          zone, \
@@ -281,16 +474,23 @@ pub async fn create(db: MySqlPool, account: u32, data: CreationData) -> AppResul
          startingEquip, \
          startingItems, \
          money, \
+         knownTitles, \
+         taximask, \
          metadata) \
-         VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)",
+         VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)",
         account,
         if data.locked { 1 } else { 0 },
-        (AtLoginFlags::FIRST_LOGIN | AtLoginFlags::CUSTOMIZE).bits,
+        data.at_login().bits,
         data.name,
         data.name_extra,
         if data.female { 1 } else { 0 },
         data.race,
         data.class,
+        data.appearance.map(|a| a.skin).unwrap_or(0),
+        data.appearance.map(|a| a.face).unwrap_or(0),
+        data.appearance.map(|a| a.hair_style).unwrap_or(0),
+        data.appearance.map(|a| a.hair_color).unwrap_or(0),
+        data.appearance.map(|a| a.facial_hair).unwrap_or(0),
         data.level,
         data.map,
         data.zone,
@@ -298,44 +498,221 @@ pub async fn create(db: MySqlPool, account: u32, data: CreationData) -> AppResul
         data.position.1,
         data.position.2,
         data.orientation,
-        make_ids_string(data.banned_spells.into_iter()),
-        make_ids_string(data.innate_spells.into_iter()),
-        make_pairs_string(data.starting_skills.into_iter()),
-        data.starting_equip.map(|e| make_equip_string(&e)),
-        make_pairs_string(data.starting_items.into_iter()),
+        make_ids_string(data.banned_spells.iter().cloned()),
+        make_ids_string(data.innate_spells.iter().cloned()),
+        make_pairs_string(data.starting_skills.iter().map(|(k, v)| (*k, *v))),
+        data.starting_equip.as_ref().map(make_equip_string),
+        make_pairs_string(data.starting_items.iter().map(|(k, v)| (*k, *v))),
         data.money,
-        serde_json::to_value(data.metadata).map_err(anyhow::Error::from)?)
-        .execute(&db)
+        make_mask_string(KNOWN_TITLES_SIZE, data.known_titles.iter().cloned()),
+        make_mask_string(TAXI_MASK_SIZE, data.taxi_nodes.iter().map(|id| id.get() - 1)),
+        data.metadata)
+        .execute(&mut *tx)
         .await?;
     Ok(done.last_insert_id() as u32)
 }
 
+/// Writes a character the way stock TrinityCore expects to find it, and what
+/// the core has no columns for into `terra_character_data`. Guids are
+/// allocated from the current maximum, which a running worldserver doesn't
+/// know about; callers hold the realm offline with `realm::hold_offline`.
+async fn insert_trinity(
+    tx: &mut Transaction<'_, MySql>,
+    catalog: &Catalog,
+    account: u32,
+    data: &CreationData,
+) -> AppResult<u32> {
+    let guid = sqlx::query!("SELECT COALESCE(MAX(guid), 0) + 1 AS guid FROM characters FOR UPDATE")
+        .fetch_one(&mut *tx)
+        .await?
+        .guid as u32;
+
+    sqlx::query!(
+        "INSERT INTO characters (\
+         guid, \
+         account, \
+         at_login, \
+         name, \
+         gender, \
+         race, \
+         class, \
+         skin, \
+         face, \
+         hairStyle, \
+         hairColor, \
+         facialStyle, \
+         level, \
+         map, \
+         zone, \
+         position_x, \
+         position_y, \
+         position_z, \
+         orientation, \
+         money, \
+         knownTitles, \
+         taximask, \
+         exploredZones, \
+         equipmentCache) \
+         VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,'','')",
+        guid,
+        account,
+        data.at_login().bits,
+        data.name,
+        if data.female { 1 } else { 0 },
+        data.race,
+        data.class,
+        data.appearance.map(|a| a.skin).unwrap_or(0),
+        data.appearance.map(|a| a.face).unwrap_or(0),
+        data.appearance.map(|a| a.hair_style).unwrap_or(0),
+        data.appearance.map(|a| a.hair_color).unwrap_or(0),
+        data.appearance.map(|a| a.facial_hair).unwrap_or(0),
+        data.level,
+        data.map,
+        data.zone,
+        data.position.0,
+        data.position.1,
+        data.position.2,
+        data.orientation,
+        data.money,
+        make_mask_string(KNOWN_TITLES_SIZE, data.known_titles.iter().cloned()),
+        make_mask_string(TAXI_MASK_SIZE, data.taxi_nodes.iter().map(|id| id.get() - 1)))
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query!(
+        "INSERT INTO terra_character_data (guid, locked, name_extra, banned_spells, metadata) \
+         VALUES (?,?,?,?,?)",
+        guid,
+        if data.locked { 1 } else { 0 },
+        data.name_extra,
+        make_ids_string(data.banned_spells.iter().cloned()),
+        data.metadata)
+        .execute(&mut *tx)
+        .await?;
+
+    for spell in &data.innate_spells {
+        sqlx::query!(
+            "INSERT INTO character_spell (guid, spell, active, disabled) VALUES (?,?,1,0)",
+            guid,
+            spell.get())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    for (skill, value) in &data.starting_skills {
+        sqlx::query!(
+            "INSERT INTO character_skills (guid, skill, value, max) VALUES (?,?,?,?)",
+            guid,
+            skill.get(),
+            value.get(),
+            value.get())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let last_item_guid = sqlx::query!("SELECT COALESCE(MAX(guid), 0) AS guid FROM item_instance FOR UPDATE")
+        .fetch_one(&mut *tx)
+        .await?
+        .guid as u32;
+
+    let stacks = make_stacks(&data.starting_items, catalog)?;
+    let inventory = make_inventory(last_item_guid, data.starting_equip.as_ref(), stacks);
+
+    for (item_guid, slot, entry, count) in inventory {
+        // the core clamps durability down to the template maximum on load
+        sqlx::query!(
+            "INSERT INTO item_instance (guid, itemEntry, owner_guid, count, charges, enchantments, durability) \
+             VALUES (?,?,?,?,'','',?)",
+            item_guid,
+            entry.get(),
+            guid,
+            count,
+            std::u16::MAX)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "INSERT INTO character_inventory (guid, bag, slot, item) VALUES (?,0,?,?)",
+            guid,
+            slot,
+            item_guid)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(guid)
+}
+
+async fn insert_progress(
+    tx: &mut Transaction<'_, MySql>,
+    guid: u32,
+    data: &CreationData,
+) -> AppResult<()> {
+    for (faction, standing) in &data.reputations {
+        sqlx::query!(
+            "INSERT INTO character_reputation (guid, faction, standing, flags) VALUES (?,?,?,?)",
+            guid,
+            faction.get(),
+            *standing,
+            FACTION_FLAG_VISIBLE)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    for quest in &data.rewarded_quests {
+        sqlx::query!(
+            "INSERT INTO character_queststatus_rewarded (guid, quest, active) VALUES (?,?,1)",
+            guid,
+            quest.get())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    if let Some(homebind) = &data.homebind {
+        sqlx::query!(
+            "INSERT INTO character_homebind (guid, mapId, zoneId, posX, posY, posZ) \
+             VALUES (?,?,?,?,?,?)",
+            guid,
+            homebind.map,
+            homebind.zone,
+            homebind.position.0,
+            homebind.position.1,
+            homebind.position.2)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
 pub async fn read(db: MySqlPool, guid: u32) -> AppResult<Data> {
     sqlx::query!(
         "SELECT \
          guid, \
+         account, \
          name, \
-         nameExtra AS name_extra, \
+         t.name_extra, \
          gender, \
          race, \
          class, \
          level, \
-         locked, \
-         stashed, \
+         COALESCE(t.locked, 0) AS locked, \
+         COALESCE(t.stashed, 0) AS stashed, \
          online, \
-         metadata \
+         t.metadata \
          FROM characters \
+         LEFT JOIN terra_character_data t USING (guid) \
          WHERE guid = ?",
         guid)
         .fetch_one(&db)
         .await
         .map(|d| Data {
             guid: d.guid,
+            account: d.account,
             name: d.name,
             name_extra: d.name_extra,
             female: d.gender != 0,
-            race: d.race,
-            class: d.class,
+            race: Some(d.race),
+            class: Some(d.class),
             level: d.level,
             locked: d.locked != 0,
             stashed: d.stashed != 0,
@@ -345,93 +722,261 @@ pub async fn read(db: MySqlPool, guid: u32) -> AppResult<Data> {
         .map_err(From::from)
 }
 
-pub async fn list_mine(db: MySqlPool, account: u32) -> AppResult<Vec<Data>> {
-    sqlx::query!(
-        "SELECT \
-         guid, \
-         name, \
-         nameExtra AS name_extra, \
-         gender, \
-         race, \
-         class, \
-         level, \
-         locked, \
-         stashed, \
-         online, \
-         metadata \
-         FROM characters \
-         WHERE account = ? AND name IS NOT NULL \
-         ORDER BY guid DESC",
-        account)
-        .fetch_all(&db)
+/// Number of characters on the account, stashed ones included.
+pub async fn count_owned(db: MySqlPool, account: u32) -> AppResult<u32> {
+    sqlx::query!("SELECT COUNT(*) AS count FROM characters WHERE account = ?", account)
+        .fetch_one(&db)
         .await
-        .map(|v| {
-            v.into_iter()
-                .map(|d| Data {
-                    guid: d.guid,
-                    name: d.name,
-                    name_extra: d.name_extra,
-                    female: d.gender != 0,
-                    race: d.race,
-                    class: d.class,
-                    level: d.level,
-                    locked: d.locked != 0,
-                    stashed: d.stashed != 0,
-                    online: d.online != 0,
-                    metadata: d.metadata.unwrap_or(JsonValue::Null),
-                })
-                .collect()
-        })
+        .map(|r| r.count as u32)
         .map_err(From::from)
 }
-pub async fn list_other(db: MySqlPool, account: u32) -> AppResult<Vec<Data>> {
-    sqlx::query!(
+
+/// Whose characters a list covers. Either way the account index is used.
+#[derive(Clone, Copy)]
+pub enum Owner {
+    Account(u32),
+    NotAccount(u32),
+}
+
+pub async fn list(
+    db: MySqlPool,
+    owner: Owner,
+    filter: &ListFilter,
+    campaign: &Campaign,
+) -> AppResult<Page<Data>> {
+    let query = ListQuery::new(owner, filter, campaign)?;
+
+    let count = format!(
+        "SELECT COUNT(*) \
+         FROM characters \
+         LEFT JOIN terra_character_data t USING (guid) \
+         WHERE {}",
+        query.clause);
+    let (total,) = query
+        .bind_to(sqlx::query_as::<_, (i64,)>(&count), None)
+        .fetch_one(&db)
+        .await?;
+
+    // one extra row tells whether there is a next page
+    let select = format!(
         "SELECT \
          guid, \
+         account, \
          name, \
-         nameExtra AS name_extra, \
+         t.name_extra, \
          gender, \
          race, \
          class, \
          level, \
-         locked, \
-         stashed, \
+         t.locked, \
+         t.stashed, \
          online, \
-         metadata \
+         t.metadata \
          FROM characters \
-         WHERE account <> ? AND name IS NOT NULL \
-         ORDER BY guid DESC",
-        account)
+         LEFT JOIN terra_character_data t USING (guid) \
+         WHERE {}{} \
+         ORDER BY guid DESC \
+         LIMIT ?",
+        query.clause,
+        if filter.cursor.is_some() { " AND guid < ?" } else { "" });
+    let items = query
+        .bind_to(sqlx::query_as::<_, ListRow>(&select), filter.cursor)
+        .bind(query.limit + 1)
         .fetch_all(&db)
-        .await
-        .map(|v| {
-            v.into_iter()
-                .map(|d| Data {
-                    guid: d.guid,
-                    name: d.name,
-                    name_extra: d.name_extra,
-                    female: d.gender != 0,
-                    race: d.race,
-                    class: d.class,
-                    level: d.level,
-                    locked: d.locked != 0,
-                    stashed: d.stashed != 0,
-                    online: d.online != 0,
-                    metadata: d.metadata.unwrap_or(JsonValue::Null),
-                })
-                .collect()
+        .await?
+        .into_iter()
+        .map(|d| Data {
+            guid: d.0,
+            account: d.1,
+            name: d.2,
+            name_extra: d.3,
+            female: d.4 != 0,
+            race: Some(d.5),
+            class: Some(d.6),
+            level: d.7,
+            locked: d.8.unwrap_or(0) != 0,
+            stashed: d.9.unwrap_or(0) != 0,
+            online: d.10 != 0,
+            metadata: d.11.unwrap_or(JsonValue::Null),
         })
-        .map_err(From::from)
+        .collect();
+
+    Ok(query.page(items, total as u64))
+}
+
+type ListRow = (
+    u32, u32, Option<String>, Option<String>, u8, u8, u8, u8,
+    Option<u8>, Option<u8>, u8, Option<JsonValue>,
+);
+
+enum ListBind {
+    Int(u32),
+    Bool(bool),
+    Text(String),
+}
+
+/// The where clause shared by the count and the page, with its binds in order.
+/// Campaign race, class and role are matched on the indexed `meta_` columns.
+struct ListQuery {
+    limit: u32,
+    clause: String,
+    binds: Vec<ListBind>,
+}
+
+impl ListQuery {
+    fn new(owner: Owner, filter: &ListFilter, campaign: &Campaign) -> AppResult<Self> {
+        let mut clause = match owner {
+            Owner::Account(_) => "account = ?",
+            Owner::NotAccount(_) => "account <> ?",
+        }
+        .to_owned();
+        let mut binds = vec![match owner {
+            Owner::Account(id) | Owner::NotAccount(id) => ListBind::Int(id),
+        }];
+        clause.push_str(" AND name IS NOT NULL");
+
+        if let Some(name) = &filter.name {
+            clause.push_str(" AND name LIKE CONCAT(?, '%')");
+            binds.push(ListBind::Text(escape_like(name)));
+        }
+        if let Some(race) = &filter.race {
+            clause.push_str(" AND t.meta_race = ?");
+            binds.push(ListBind::Text(race.clone()));
+        }
+        if let Some(class) = &filter.class {
+            clause.push_str(" AND t.meta_class = ?");
+            binds.push(ListBind::Text(class.clone()));
+        }
+        if let Some(roles) = filter.roles(campaign)? {
+            if roles.is_empty() {
+                clause.push_str(" AND FALSE");
+            } else {
+                let marks = vec!["?"; roles.len()].join(", ");
+                clause.push_str(&format!(" AND t.meta_role IN ({})", marks));
+                binds.extend(roles.into_iter().map(ListBind::Text));
+            }
+        }
+        if let Some(locked) = filter.locked {
+            clause.push_str(" AND COALESCE(t.locked, 0) = ?");
+            binds.push(ListBind::Bool(locked));
+        }
+        if let Some(stashed) = filter.stashed {
+            clause.push_str(" AND COALESCE(t.stashed, 0) = ?");
+            binds.push(ListBind::Bool(stashed));
+        }
+        if let Some(online) = filter.online {
+            clause.push_str(" AND online = ?");
+            binds.push(ListBind::Bool(online));
+        }
+
+        Ok(Self {
+            limit: filter.limit.unwrap_or(LIST_LIMIT_DEFAULT).min(LIST_LIMIT_MAX).max(1),
+            clause,
+            binds,
+        })
+    }
+
+    /// Binds the clause's values, then the cursor when the query pages by one.
+    fn bind_to<'q, O>(
+        &'q self,
+        mut query: QueryAs<'q, MySql, O, MySqlArguments>,
+        cursor: Option<u32>,
+    ) -> QueryAs<'q, MySql, O, MySqlArguments> {
+        for bind in &self.binds {
+            query = match bind {
+                ListBind::Int(value) => query.bind(*value),
+                ListBind::Bool(value) => query.bind(*value),
+                ListBind::Text(value) => query.bind(value.as_str()),
+            };
+        }
+        match cursor {
+            Some(cursor) => query.bind(cursor),
+            None => query,
+        }
+    }
+
+    fn page(&self, mut items: Vec<Data>, total: u64) -> Page<Data> {
+        let next = if items.len() > self.limit as usize {
+            items.truncate(self.limit as usize);
+            items.last().map(|d| d.guid)
+        } else {
+            None
+        };
+        Page { items, total, next }
+    }
 }
 
 pub async fn check_name(db: MySqlPool, name: &str) -> AppResult<bool> {
-    sqlx::query!("SELECT COUNT(*) AS num FROM characters WHERE name = ?", util::prepare_name(name))
+    let name = util::prepare_name(name);
+    sqlx::query!(
+        "SELECT COUNT(*) AS num FROM (\
+         SELECT name FROM characters WHERE name = ? \
+         UNION ALL \
+         SELECT name FROM terra_name_reservations WHERE name = ?) AS taken",
+        name,
+        name)
         .fetch_one(&db)
         .await
         .map(|r| r.num == 0)
         .map_err(From::from)
 }
 
+/// Normalizes a character name and checks it against the naming rules.
+pub fn prepare_name(input: &str) -> AppResult<String> {
+    let name = util::prepare_name(input);
+    if NAME_REGEX.is_match(&name) {
+        Ok(name)
+    } else {
+        Err(AppError::InvalidInput("name"))
+    }
+}
+
+pub fn check_name_extra(input: Option<&str>) -> AppResult<Option<String>> {
+    let name_extra = util::prepare_name_extra(input);
+    match &name_extra {
+        Some(s) if !NAME_EXTRA_REGEX.is_match(s) => Err(AppError::InvalidInput("name_extra")),
+        _ => Ok(name_extra),
+    }
+}
+
+fn appearance_allowed(
+    appearance: &Appearance,
+    race: &Race,
+    female: bool,
+    client: Option<&ClientData>,
+) -> bool {
+    let options = client.and_then(|c| c.appearance.get(&(race.game_id, female)));
+    let limits = race
+        .customization
+        .as_ref()
+        .and_then(|c| if female { c.female } else { c.male });
+    if let Some(options) = options {
+        options.allows(
+            appearance.skin,
+            appearance.face,
+            appearance.hair_style,
+            appearance.hair_color,
+            appearance.facial_hair,
+        )
+    } else if let Some(limits) = limits {
+        appearance.fits(&limits)
+    } else {
+        // nothing to check against, leave it to the core
+        true
+    }
+}
+
+fn escape_like(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    for c in input.trim().chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            output.push('\\');
+        }
+        output.push(c);
+    }
+    output
+}
+
 fn make_pairs_map(src: &HashMap<NonZeroU32, i32>) -> HashMap<NonZeroU32, NonZeroU32> {
     src.into_iter()
         .flat_map(|(id, value)| {
@@ -515,6 +1060,54 @@ fn make_equip_string(input: &EquipArray) -> String {
     b.result()
 }
 
+/// Item guid, backpack slot, entry and count of each item a new character
+/// starts with: equipment in its own slots, then stacks in the backpack. Guids
+/// count up from `last_guid`.
+fn make_inventory(
+    last_guid: u32,
+    equip: Option<&EquipArray>,
+    stacks: Vec<(NonZeroU32, u32)>,
+) -> Vec<(u32, u8, NonZeroU32, u32)> {
+    let equipped = equip
+        .into_iter()
+        .flat_map(|equip| equip.iter().enumerate())
+        .filter_map(|(slot, maybe_id)| maybe_id.map(|id| (slot, id, 1)));
+    let carried = (INVENTORY_SLOT_ITEM_START..)
+        .zip(stacks)
+        .map(|(slot, (id, count))| (slot, id, count));
+    (last_guid + 1..)
+        .zip(equipped.chain(carried))
+        .map(|(guid, (slot, id, count))| (guid, slot as u8, id, count))
+        .collect()
+}
+
+/// Splits item counts into one stack per bag slot, by item id. Counts above one
+/// need the stack size from the catalog.
+fn make_stacks(
+    items: &HashMap<NonZeroU32, NonZeroU32>,
+    catalog: &Catalog,
+) -> AppResult<Vec<(NonZeroU32, u32)>> {
+    let mut ids: Vec<_> = items.keys().cloned().collect();
+    ids.sort();
+    let mut stacks = Vec::new();
+    for id in ids {
+        let mut count = items[&id].get();
+        let stack = match catalog.items.get(&id.get()).and_then(|i| i.stack) {
+            Some(stack) => max(stack, 1),
+            None if count == 1 => 1,
+            None => return Err(AppError::InvalidInput("items")),
+        };
+        while count > 0 {
+            if stacks.len() == INVENTORY_SLOT_ITEM_END - INVENTORY_SLOT_ITEM_START {
+                return Err(AppError::InvalidInput("items"));
+            }
+            stacks.push((id, min(count, stack)));
+            count -= min(count, stack);
+        }
+    }
+    Ok(stacks)
+}
+
 fn make_ids_string(input: impl IntoIterator<Item = NonZeroU32>) -> Option<String> {
     let mut b = StringBuilder::new();
     for id in input {
@@ -531,3 +1124,91 @@ fn make_pairs_string(input: impl IntoIterator<Item = (NonZeroU32, NonZeroU32)>)
     }
     b.result_option()
 }
+
+/// Maps title ids onto their `knownTitles` bits through CharTitles.dbc. Titles
+/// can't be granted without it.
+fn make_title_bits(
+    titles: &HashSet<NonZeroU32>,
+    client: Option<&ClientData>,
+) -> AppResult<HashSet<u32>> {
+    titles
+        .iter()
+        .map(|id| {
+            client
+                .and_then(|client| client.title_bits.get(&id.get()).cloned())
+                .filter(|&bit| (bit as usize) < KNOWN_TITLES_SIZE * 32)
+                .ok_or(AppError::InvalidInput("titles"))
+        })
+        .collect()
+}
+
+fn make_mask_string(size: usize, bits: impl IntoIterator<Item = u32>) -> String {
+    // bits are 0-based; titles are mapped to theirs already, taxi node ids are off by one
+    let mut mask = vec![0u32; size];
+    for bit in bits {
+        if let Some(field) = mask.get_mut(bit as usize / 32) {
+            *field |= 1 << (bit % 32);
+        }
+    }
+    let mut b = StringBuilder::new();
+    for field in mask {
+        b.write(field);
+    }
+    b.result()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framework::catalog::ItemInfo;
+
+    fn id(id: u32) -> NonZeroU32 {
+        NonZeroU32::new(id).unwrap()
+    }
+
+    #[test]
+    fn trinity_inventory() {
+        let mut catalog = Catalog::new();
+        catalog.items.insert(6948, ItemInfo {
+            name: "Hearthstone".to_owned(),
+            quality: 1,
+            slot: 0,
+            icon: None,
+            stack: Some(1),
+        });
+        catalog.items.insert(159, ItemInfo {
+            name: "Refreshing Spring Water".to_owned(),
+            quality: 1,
+            slot: 0,
+            icon: None,
+            stack: Some(20),
+        });
+        let mut items = HashMap::new();
+        items.insert(id(159), id(45));
+        items.insert(id(6948), id(1));
+        let stacks = make_stacks(&items, &catalog).unwrap();
+        assert_eq!(stacks, vec![(id(159), 20), (id(159), 20), (id(159), 5), (id(6948), 1)]);
+
+        // unknown stack sizes only pass for single items
+        items.insert(id(2512), id(200));
+        assert!(make_stacks(&items, &catalog).is_err());
+        items.insert(id(2512), id(1));
+        assert!(make_stacks(&items, &catalog).is_ok());
+        items.insert(id(159), id(20 * 17));
+        assert!(make_stacks(&items, &catalog).is_err());
+
+        let mut equip = [None; EQUIP_SLOTS];
+        equip[4] = Some(id(38));
+        equip[15] = Some(id(25));
+        let inventory = make_inventory(100, Some(&equip), stacks);
+        assert_eq!(inventory, vec![
+            (101, 4, id(38), 1),
+            (102, 15, id(25), 1),
+            (103, 23, id(159), 20),
+            (104, 24, id(159), 20),
+            (105, 25, id(159), 5),
+            (106, 26, id(6948), 1),
+        ]);
+        assert!(make_inventory(7, None, Vec::new()).is_empty());
+    }
+}
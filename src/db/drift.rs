@@ -0,0 +1,250 @@
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use sqlx::{mysql::MySqlPool, prelude::*};
+use crate::{
+    db::character::Metadata,
+    error::AppResult,
+    framework::{
+        campaign::Campaign,
+        system::Metadata as Definition,
+        tags::Tags,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Issue {
+    /// The id is no longer defined.
+    Missing,
+    /// The definition maps to another race or class than the character has.
+    Changed,
+    /// The definition requires tags the character no longer provides.
+    Condition,
+    /// The stored metadata can't be read at all, the id holds the reason.
+    Unreadable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Problem {
+    pub field: &'static str,
+    pub id: String,
+    pub issue: Issue,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Drifted {
+    pub guid: u32,
+    pub account: u32,
+    pub name: String,
+    /// Campaign the character was created with, unknown for older characters.
+    pub campaign_version: Option<String>,
+    /// Created with a campaign other than the current one.
+    pub outdated: bool,
+    pub problems: Vec<Problem>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub campaign_version: String,
+    pub checked: u32,
+    pub characters: Vec<Drifted>,
+}
+
+/// Checks every character created through terra against the current campaign.
+/// Characters with problems and those created with another campaign version
+/// are reported.
+pub async fn scan(db: MySqlPool, campaign: &Campaign) -> AppResult<Report> {
+    let rows = sqlx::query!(
+        "SELECT guid, account, name, gender, race, class, t.metadata \
+         FROM characters \
+         JOIN terra_character_data t USING (guid) \
+         WHERE t.metadata IS NOT NULL \
+         ORDER BY guid")
+        .fetch_all(&db)
+        .await?;
+    let mut report = Report {
+        campaign_version: campaign.version.clone(),
+        checked: 0,
+        characters: Vec::new(),
+    };
+    for row in rows {
+        report.checked += 1;
+        let parsed: Result<Metadata, _> =
+            serde_json::from_value(row.metadata.unwrap_or(JsonValue::Null));
+        let (campaign_version, problems) = match parsed {
+            Ok(metadata) => {
+                let problems = check(campaign, row.gender != 0, row.race, row.class, &metadata);
+                (metadata.campaign_version, problems)
+            }
+            Err(err) => {
+                let problem = Problem {
+                    field: "metadata",
+                    id: err.to_string(),
+                    issue: Issue::Unreadable,
+                };
+                (None, vec![problem])
+            }
+        };
+        let outdated = campaign_version
+            .as_ref()
+            .map_or(false, |version| version != &campaign.version);
+        if outdated || !problems.is_empty() {
+            report.characters.push(Drifted {
+                guid: row.guid,
+                account: row.account,
+                name: row.name,
+                campaign_version,
+                outdated,
+                problems,
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Resolves a character's ids like creation does and reports what no longer fits.
+pub fn check(
+    campaign: &Campaign,
+    female: bool,
+    race: u8,
+    class: u8,
+    metadata: &Metadata,
+) -> Vec<Problem> {
+    let mut checker = Checker::default();
+    checker
+        .tags
+        .add(if female { "gender/female" } else { "gender/male" }, 1);
+
+    if let Some(id) = &metadata.role {
+        match campaign.roles.get(id) {
+            Some(role) => checker.tags.merge_in(&role.provides),
+            None => checker.problem("role", id, Issue::Missing),
+        }
+    }
+    if let Some(id) = &metadata.race {
+        let found = checker.resolve("race", id, campaign.system.race.get(id));
+        if found.map_or(false, |found| found.game_id != race) {
+            checker.problem("race", id, Issue::Changed);
+        }
+    }
+    if let Some(id) = &metadata.class {
+        let found = checker.resolve("class", id, campaign.system.class.get(id));
+        if found.map_or(false, |found| found.game_id != class) {
+            checker.problem("class", id, Issue::Changed);
+        }
+    }
+    if let Some(id) = &metadata.armor {
+        checker.resolve("armor", id, campaign.system.armor.get(id));
+    }
+    if let Some(id) = &metadata.weapon {
+        checker.resolve("weapon", id, campaign.system.weapon.get(id));
+    }
+    if let Some(traits) = &metadata.traits {
+        let mut ids: Vec<_> = traits.iter().collect();
+        ids.sort();
+        for id in ids {
+            checker.resolve("traits", id, campaign.system.traits.get(id));
+        }
+    }
+    if let Some(id) = &metadata.location {
+        checker.resolve("location", id, campaign.system.location.get(id));
+    }
+
+    // conditions are checked once all tags are in, as on creation
+    let Checker { tags, found, mut problems } = checker;
+    for (field, id, definition) in found {
+        if let Some(requires) = &definition.requires {
+            if !requires.check(&tags) {
+                problems.push(Problem { field, id, issue: Issue::Condition });
+            }
+        }
+    }
+    problems
+}
+
+#[derive(Default)]
+struct Checker<'a> {
+    tags: Tags,
+    found: Vec<(&'static str, String, &'a Definition)>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    fn resolve<T: AsRef<Definition>>(
+        &mut self,
+        field: &'static str,
+        id: &str,
+        entity: Option<&'a T>,
+    ) -> Option<&'a T> {
+        match entity {
+            Some(entity) => {
+                let definition = entity.as_ref();
+                self.tags.merge_in(&definition.provides);
+                self.found.push((field, id.to_owned(), definition));
+            }
+            None => self.problem(field, id, Issue::Missing),
+        }
+        entity
+    }
+
+    fn problem(&mut self, field: &'static str, id: &str, issue: Issue) {
+        self.problems.push(Problem { field, id: id.to_owned(), issue });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::framework::{
+        campaign::Editing,
+        catalog::Catalog,
+        system::System,
+        visibility::Visibility,
+    };
+
+    fn campaign() -> Campaign {
+        let system: System = serde_yaml::from_str(
+            "race:\n\
+             \x20 human: { name: Human, game_id: 1 }\n\
+             class:\n\
+             \x20 warrior: { name: Warrior, game_id: 1 }\n\
+             \x20 priest: { name: Priest, game_id: 5, requires: { has: gender/female } }\n",
+        )
+        .unwrap();
+        Campaign {
+            version: "0123456789ABCDEF".to_owned(),
+            name: "Test".to_owned(),
+            info: String::new(),
+            system_view: system.view(&Catalog::new()),
+            system,
+            blocks: Vec::new(),
+            roles: Default::default(),
+            visibility: Visibility::default(),
+            editing: Editing::default(),
+        }
+    }
+
+    fn metadata(race: &str, class: &str) -> Metadata {
+        serde_json::from_value(serde_json::json!({ "race": race, "class": class })).unwrap()
+    }
+
+    #[test]
+    fn campaign_drift() {
+        let campaign = campaign();
+        assert!(check(&campaign, false, 1, 1, &metadata("human", "warrior")).is_empty());
+        assert!(check(&campaign, true, 1, 5, &metadata("human", "priest")).is_empty());
+
+        let problem = |field, id: &str, issue| Problem { field, id: id.to_owned(), issue };
+        assert_eq!(
+            check(&campaign, false, 1, 1, &metadata("orc", "warrior")),
+            vec![problem("race", "orc", Issue::Missing)]
+        );
+        assert_eq!(
+            check(&campaign, false, 1, 1, &metadata("human", "priest")),
+            vec![
+                problem("class", "priest", Issue::Changed),
+                problem("class", "priest", Issue::Condition),
+            ]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use sqlx::{mysql::MySqlPool, prelude::*};
+use crate::error::{AppError, AppResult};
+use super::account;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Invite {
+    pub code: String,
+    pub created_by: u32,
+    pub created_at: u32,
+    pub expires_at: Option<u32>,
+    pub max_uses: u32,
+    pub uses: u32,
+    /// Realm whose campaign has the role or block held for the invited accounts.
+    pub realm: Option<u32>,
+    pub role: Option<String>,
+    pub block: Option<String>,
+    pub revoked_at: Option<u32>,
+    /// Accounts created with the code.
+    pub accounts: Vec<u32>,
+}
+
+pub async fn create(
+    db: MySqlPool,
+    code: &str,
+    gm: u32,
+    expires_in: Option<u32>,
+    max_uses: u32,
+    realm: Option<u32>,
+    role: Option<&str>,
+    block: Option<&str>,
+) -> AppResult<Invite> {
+    sqlx::query!(
+        "INSERT INTO terra_invite_codes \
+         (code, created_by, created_at, expires_at, max_uses, uses, realm, role, block) \
+         VALUES (?,?,UNIX_TIMESTAMP(),UNIX_TIMESTAMP() + ?,?,0,?,?,?)",
+        code,
+        gm,
+        expires_in,
+        max_uses,
+        realm,
+        role,
+        block)
+        .execute(&db)
+        .await?;
+    list(db)
+        .await?
+        .into_iter()
+        .find(|invite| invite.code == code)
+        .ok_or(AppError::NotFound)
+}
+
+pub async fn list(db: MySqlPool) -> AppResult<Vec<Invite>> {
+    let mut accounts: HashMap<String, Vec<u32>> = HashMap::new();
+    for row in sqlx::query!("SELECT code, account FROM terra_invite_uses ORDER BY used_at")
+        .fetch_all(&db)
+        .await?
+    {
+        accounts.entry(row.code).or_default().push(row.account);
+    }
+    sqlx::query!(
+        "SELECT code, created_by, created_at, expires_at, max_uses, uses, realm, role, block, \
+         revoked_at \
+         FROM terra_invite_codes \
+         ORDER BY created_at DESC")
+        .fetch_all(&db)
+        .await
+        .map(|v| {
+            v.into_iter()
+                .map(|r| Invite {
+                    accounts: accounts.remove(&r.code).unwrap_or_default(),
+                    code: r.code,
+                    created_by: r.created_by,
+                    created_at: r.created_at,
+                    expires_at: r.expires_at,
+                    max_uses: r.max_uses,
+                    uses: r.uses,
+                    realm: r.realm,
+                    role: r.role,
+                    block: r.block,
+                    revoked_at: r.revoked_at,
+                })
+                .collect()
+        })
+        .map_err(From::from)
+}
+
+/// Stops a code from being used. Roles it reserved are released unless an
+/// account was created with it already.
+pub async fn revoke(db: MySqlPool, code: &str) -> AppResult<()> {
+    let done = sqlx::query!(
+        "UPDATE terra_invite_codes SET revoked_at = UNIX_TIMESTAMP() \
+         WHERE code = ? AND revoked_at IS NULL",
+        code)
+        .execute(&db)
+        .await?;
+    if done.rows_affected() == 0 {
+        Err(AppError::NotFound)
+    } else {
+        Ok(())
+    }
+}
+
+/// Creates an account using up one use of `code`. Both happen or neither does.
+pub async fn create_account(
+    db: MySqlPool,
+    code: &str,
+    username: &str,
+    password: &str,
+    email: &str,
+) -> AppResult<u32> {
+    let mut tx = db.begin().await?;
+    sqlx::query!(
+        "SELECT code FROM terra_invite_codes \
+         WHERE code = ? AND revoked_at IS NULL AND uses < max_uses \
+         AND (expires_at IS NULL OR expires_at > UNIX_TIMESTAMP()) \
+         FOR UPDATE",
+        code)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AppError::InvalidInput("invite"))?;
+    let id = account::insert(&mut tx, username, password, email).await?;
+    sqlx::query!("UPDATE terra_invite_codes SET uses = uses + 1 WHERE code = ?", code)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO terra_invite_uses (code, account, used_at) VALUES (?,?,UNIX_TIMESTAMP())",
+        code,
+        id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(id)
+}
+
+/// Whether `account` may take a role on a realm. Roles and blocks named by a
+/// live invite, or one that was used before being revoked, are held for the
+/// accounts created with it.
+pub async fn role_available(
+    db: MySqlPool,
+    realm: u32,
+    account: u32,
+    role: &str,
+    block: Option<&str>,
+) -> AppResult<bool> {
+    sqlx::query!(
+        "SELECT COUNT(*) AS reserved, \
+         COUNT(u.account) AS held \
+         FROM terra_invite_codes c \
+         LEFT JOIN terra_invite_uses u ON u.code = c.code AND u.account = ? \
+         WHERE c.realm = ? \
+         AND (c.role = ? OR c.block = ?) \
+         AND (c.revoked_at IS NULL OR c.uses > 0) \
+         AND (c.uses > 0 OR c.expires_at IS NULL OR c.expires_at > UNIX_TIMESTAMP())",
+        account,
+        realm,
+        role,
+        block)
+        .fetch_one(&db)
+        .await
+        .map(|r| r.reserved == 0 || r.held > 0)
+        .map_err(From::from)
+}
@@ -31,5 +31,33 @@ impl DBConfig {
     }
 }
 
+/// Which characters schema terra writes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Skyland core with starting spells, skills and equipment kept in custom columns.
+    Skyland,
+    /// Stock TrinityCore/AzerothCore. The character goes into the core's own tables,
+    /// what the core has no columns for into `terra_character_data`.
+    Trinity,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Skyland
+    }
+}
+
 pub mod account;
+pub mod at_login;
+pub mod audit;
+pub mod ban;
+pub mod biography;
 pub mod character;
+pub mod drift;
+pub mod invite;
+pub mod rbac;
+pub mod realm;
+pub mod rename;
+pub mod token;
+pub mod world;
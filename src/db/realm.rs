@@ -0,0 +1,91 @@
+use serde::Serialize;
+use sqlx::{mysql::{MySql, MySqlPool}, prelude::*, Transaction};
+use crate::error::{AppError, AppResult};
+
+const REALM_FLAG_OFFLINE: u8 = 0x02;
+
+/// A row of the auth database's `realmlist`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Realm {
+    pub id: u32,
+    pub name: String,
+    pub address: String,
+    pub port: u16,
+    pub flags: u8,
+    pub population: f32,
+    /// Live counts, for realms whose characters database terra knows.
+    pub status: Option<Status>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Status {
+    pub online: u32,
+    pub characters: u32,
+    /// Characters waiting for a GM: locked ones and those with a biography
+    /// revision to approve.
+    pub pending_approval: u32,
+}
+
+pub async fn list(db: MySqlPool) -> AppResult<Vec<Realm>> {
+    sqlx::query!(
+        "SELECT id, name, address, port, flag, population \
+         FROM realmlist \
+         ORDER BY id")
+        .fetch_all(&db)
+        .await
+        .map(|v| {
+            v.into_iter()
+                .map(|r| Realm {
+                    id: r.id,
+                    name: r.name,
+                    address: r.address,
+                    port: r.port,
+                    flags: r.flag,
+                    population: r.population,
+                    status: None,
+                })
+                .collect()
+        })
+        .map_err(From::from)
+}
+
+/// Locks the realm's row while its worldserver is down, which keeps it from
+/// marking itself online until the transaction ends. Fails with
+/// `AppError::RealmOnline` while the worldserver runs.
+pub async fn hold_offline(db: MySqlPool, id: u32) -> AppResult<Transaction<'static, MySql>> {
+    let mut tx = db.begin().await?;
+    let flag = sqlx::query!("SELECT flag FROM realmlist WHERE id = ? FOR UPDATE", id)
+        .fetch_one(&mut tx)
+        .await?
+        .flag;
+    if flag & REALM_FLAG_OFFLINE == 0 {
+        return Err(AppError::RealmOnline);
+    }
+    Ok(tx)
+}
+
+/// Counts from a realm's characters database.
+pub async fn status(db: MySqlPool) -> AppResult<Status> {
+    let row = sqlx::query!(
+        "SELECT COUNT(*) AS characters, CAST(COALESCE(SUM(online <> 0), 0) AS UNSIGNED) AS online \
+         FROM characters")
+        .fetch_one(&db)
+        .await?;
+    // a revision waits when it is newer than the last approved one
+    let pending = sqlx::query!(
+        "SELECT COUNT(*) AS pending \
+         FROM characters \
+         LEFT JOIN terra_character_data t USING (guid) \
+         WHERE COALESCE(t.locked, 0) = 1 OR guid IN ( \
+             SELECT r.guid FROM terra_character_revisions r \
+             WHERE r.approved = 0 AND r.id > COALESCE(( \
+                 SELECT MAX(a.id) FROM terra_character_revisions a \
+                 WHERE a.guid = r.guid AND a.approved = 1), 0))")
+        .fetch_one(&db)
+        .await?;
+    Ok(Status {
+        online: row.online as u32,
+        characters: row.characters as u32,
+        pending_approval: pending.pending as u32,
+    })
+}
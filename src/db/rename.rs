@@ -0,0 +1,137 @@
+use log::warn;
+use serde::Serialize;
+use sqlx::{mysql::MySqlPool, prelude::*};
+use crate::error::{AppError, AppResult};
+use super::{at_login, character::AtLoginFlags};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenameStatus {
+    /// The character was offline and carries the new name already.
+    Applied,
+    /// The name is reserved and applied once the character logs out.
+    Queued,
+}
+
+/// Renames a character, which must belong to `owner` if one is given. `name`
+/// must be prepared already.
+pub async fn request(
+    db: MySqlPool,
+    guid: u32,
+    owner: Option<u32>,
+    name: &str,
+) -> AppResult<RenameStatus> {
+    let mut tx = db.begin().await?;
+
+    let character = sqlx::query!(
+        "SELECT account, online FROM characters WHERE guid = ? FOR UPDATE",
+        guid)
+        .fetch_one(&mut tx)
+        .await?;
+    if owner.map_or(false, |owner| owner != character.account) {
+        return Err(AppError::Forbidden);
+    }
+
+    let taken = sqlx::query!(
+        "SELECT COUNT(*) AS num FROM (\
+         SELECT guid FROM characters WHERE name = ? AND guid <> ? \
+         UNION ALL \
+         SELECT guid FROM terra_name_reservations WHERE name = ? AND guid <> ?) AS taken",
+        name,
+        guid,
+        name,
+        guid)
+        .fetch_one(&mut tx)
+        .await?
+        .num;
+    if taken != 0 {
+        return Err(AppError::Conflict);
+    }
+
+    sqlx::query!("DELETE FROM terra_name_reservations WHERE guid = ?", guid)
+        .execute(&mut tx)
+        .await?;
+
+    let status = if character.online == 0 {
+        sqlx::query!(
+            "UPDATE characters SET name = ?, at_login = at_login & ~? WHERE guid = ?",
+            name,
+            AtLoginFlags::RENAME.bits,
+            guid)
+            .execute(&mut tx)
+            .await?;
+        RenameStatus::Applied
+    } else {
+        sqlx::query!(
+            "INSERT INTO terra_name_reservations (name, guid, created_at) \
+             VALUES (?,?,UNIX_TIMESTAMP())",
+            name,
+            guid)
+            .execute(&mut tx)
+            .await?;
+        RenameStatus::Queued
+    };
+
+    tx.commit().await?;
+    Ok(status)
+}
+
+/// Makes the core ask for a new name on the next login. Online characters are
+/// refused like any at-login change, the core would drop the flag on logout.
+pub async fn force(db: MySqlPool, gm: u32, guid: u32) -> AppResult<()> {
+    let outcomes =
+        at_login::update(db, gm, &[guid], AtLoginFlags::RENAME, AtLoginFlags::empty()).await?;
+    match outcomes.first().map(|o| o.status) {
+        Some(at_login::Status::Updated) => Ok(()),
+        Some(at_login::Status::Online) => Err(AppError::Conflict),
+        _ => Err(AppError::NotFound),
+    }
+}
+
+/// Applies queued renames of characters that are offline by now. A name taken
+/// by another character meanwhile is dropped instead. A running worldserver
+/// keeps old names in its cache until it is restarted.
+pub async fn apply_queued(db: MySqlPool) -> AppResult<u64> {
+    let mut tx = db.begin().await?;
+    let queued = sqlx::query!(
+        "SELECT r.guid, r.name FROM terra_name_reservations r \
+         JOIN characters c ON c.guid = r.guid \
+         WHERE c.online = 0 \
+         FOR UPDATE")
+        .fetch_all(&mut tx)
+        .await?;
+
+    let mut applied = 0;
+    for rename in queued {
+        let taken = sqlx::query!(
+            "SELECT COUNT(*) AS num FROM characters WHERE name = ? AND guid <> ?",
+            rename.name,
+            rename.guid)
+            .fetch_one(&mut tx)
+            .await?
+            .num;
+        if taken != 0 {
+            warn!(
+                "Dropping queued rename of {} to {}, the name is taken",
+                rename.guid,
+                rename.name
+            );
+        } else {
+            applied += sqlx::query!(
+                "UPDATE characters SET name = ?, at_login = at_login & ~? \
+                 WHERE guid = ? AND online = 0",
+                rename.name,
+                AtLoginFlags::RENAME.bits,
+                rename.guid)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+        }
+        sqlx::query!("DELETE FROM terra_name_reservations WHERE guid = ?", rename.guid)
+            .execute(&mut tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(applied)
+}
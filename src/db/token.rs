@@ -0,0 +1,154 @@
+use ring::{constant_time, hmac};
+use sqlx::{mysql::MySqlPool, prelude::*};
+use crate::{
+    error::{AppError, AppResult},
+    util,
+};
+
+const NONCE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    /// Confirms that the account owns an email address.
+    Verify,
+    /// Sets a new password without knowing the old one.
+    Reset,
+}
+
+impl Purpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Purpose::Verify => "verify",
+            Purpose::Reset => "reset",
+        }
+    }
+}
+
+/// What a consumed token was issued for.
+pub struct Token {
+    pub account: u32,
+    pub email: Option<String>,
+}
+
+/// Issues a single-use token valid for `ttl` seconds, written as `nonce.signature`.
+pub async fn issue(
+    db: MySqlPool,
+    secret: &str,
+    account: u32,
+    purpose: Purpose,
+    email: Option<&str>,
+    ttl: u64,
+) -> AppResult<String> {
+    let nonce = util::random_hex(NONCE_SIZE)?;
+    sqlx::query!(
+        "INSERT INTO terra_account_tokens (nonce, account, purpose, email, expires_at) \
+         VALUES (?,?,?,?,UNIX_TIMESTAMP() + ?)",
+        nonce,
+        account,
+        purpose.as_str(),
+        email,
+        ttl)
+        .execute(&db)
+        .await?;
+    Ok(format!("{}.{}", nonce, sign(secret, purpose, &nonce)))
+}
+
+/// Drops the unused tokens of an account, e.g. other reset links once one worked.
+pub async fn revoke(db: MySqlPool, account: u32, purpose: Purpose) -> AppResult<()> {
+    sqlx::query!(
+        "DELETE FROM terra_account_tokens WHERE account = ? AND purpose = ? AND used_at IS NULL",
+        account,
+        purpose.as_str())
+        .execute(&db)
+        .await
+        .map(|_| ())
+        .map_err(From::from)
+}
+
+/// Looks a token up without using it.
+pub async fn peek(
+    db: MySqlPool,
+    secret: &str,
+    token: &str,
+    purpose: Purpose,
+) -> AppResult<Token> {
+    let nonce = verify(secret, token, purpose)?;
+    sqlx::query!(
+        "SELECT account, email FROM terra_account_tokens \
+         WHERE nonce = ? AND purpose = ? AND used_at IS NULL AND expires_at > UNIX_TIMESTAMP()",
+        nonce,
+        purpose.as_str())
+        .fetch_optional(&db)
+        .await?
+        .map(|r| Token {
+            account: r.account,
+            email: r.email,
+        })
+        .ok_or(AppError::InvalidInput("token"))
+}
+
+/// Marks a token used. Forged, expired and used tokens are all rejected alike.
+pub async fn consume(
+    db: MySqlPool,
+    secret: &str,
+    token: &str,
+    purpose: Purpose,
+) -> AppResult<Token> {
+    let nonce = verify(secret, token, purpose)?;
+    let mut tx = db.begin().await?;
+    let row = sqlx::query!(
+        "SELECT account, email FROM terra_account_tokens \
+         WHERE nonce = ? AND purpose = ? AND used_at IS NULL AND expires_at > UNIX_TIMESTAMP() \
+         FOR UPDATE",
+        nonce,
+        purpose.as_str())
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AppError::InvalidInput("token"))?;
+    sqlx::query!(
+        "UPDATE terra_account_tokens SET used_at = UNIX_TIMESTAMP() WHERE nonce = ?",
+        nonce)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(Token {
+        account: row.account,
+        email: row.email,
+    })
+}
+
+/// Checks the signature and returns the nonce.
+fn verify<'a>(secret: &str, token: &'a str, purpose: Purpose) -> AppResult<&'a str> {
+    let mut parts = token.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(nonce), Some(signature))
+            if constant_time::verify_slices_are_equal(
+                sign(secret, purpose, nonce).as_bytes(),
+                signature.to_uppercase().as_bytes(),
+            )
+            .is_ok() =>
+        {
+            Ok(nonce)
+        }
+        _ => Err(AppError::InvalidInput("token")),
+    }
+}
+
+fn sign(secret: &str, purpose: Purpose, nonce: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let message = format!("{}:{}", purpose.as_str(), nonce);
+    util::hexstring(hmac::sign(&key, message.as_bytes()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn token_signature() {
+        let token = format!("00FF.{}", sign("s3cr3t", Purpose::Reset, "00FF"));
+        assert_eq!(verify("s3cr3t", &token, Purpose::Reset).ok(), Some("00FF"));
+        assert!(verify("s3cr3t", &token, Purpose::Verify).is_err());
+        assert!(verify("other", &token, Purpose::Reset).is_err());
+        assert!(verify("s3cr3t", "00FF", Purpose::Reset).is_err());
+    }
+}
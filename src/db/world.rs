@@ -0,0 +1,70 @@
+use std::collections::{HashMap, HashSet};
+use log::warn;
+use serde::Deserialize;
+use sqlx::mysql::MySqlPool;
+use crate::framework::catalog::{Catalog, ItemInfo};
+use super::DBConfig;
+
+#[derive(Deserialize)]
+pub struct WorldDBConfig {
+    #[serde(flatten)] pub db: DBConfig,
+    /// Table holding every spell id in its `ID` column, e.g. an imported
+    /// Spell.dbc. Stock cores only keep serverside spells in `spell_dbc`.
+    #[serde(default)] pub spell_table: Option<String>,
+    /// Table holding every skill line id in its `ID` column.
+    #[serde(default)] pub skill_table: Option<String>,
+}
+
+/// Ids known to the world database. Spells and skills are `None` when their
+/// tables are not configured or could not be read, and are then not checked.
+#[derive(Debug, Default)]
+pub struct WorldIndex {
+    pub items: HashMap<u32, ItemInfo>,
+    pub spells: Option<HashSet<u32>>,
+    pub skills: Option<HashSet<u32>>,
+}
+
+pub async fn load_index(db: &MySqlPool, config: &WorldDBConfig) -> anyhow::Result<WorldIndex> {
+    let items = sqlx::query_as::<_, (u32, String, u8, u8, i32)>(
+        "SELECT entry, name, Quality, InventoryType, stackable FROM item_template",
+    )
+    .fetch_all(db)
+    .await?
+    .into_iter()
+    .map(|(entry, name, quality, slot, stack)| {
+        let stack = Some(stack.max(1) as u32);
+        (entry, ItemInfo { name, quality, slot, icon: None, stack })
+    })
+    .collect();
+    let spells = match &config.spell_table {
+        Some(table) => load_ids(db, table).await,
+        None => None,
+    };
+    let skills = match &config.skill_table {
+        Some(table) => load_ids(db, table).await,
+        None => None,
+    };
+    Ok(WorldIndex { items, spells, skills })
+}
+
+impl WorldIndex {
+    pub fn catalog(&self) -> Catalog {
+        Catalog {
+            items: self.items.clone(),
+            ..Catalog::new()
+        }
+    }
+}
+
+async fn load_ids(db: &MySqlPool, table: &str) -> Option<HashSet<u32>> {
+    match sqlx::query_as::<_, (u32,)>(&format!("SELECT ID FROM {}", table))
+        .fetch_all(db)
+        .await
+    {
+        Ok(rows) => Some(rows.into_iter().map(|(id,)| id).collect()),
+        Err(err) => {
+            warn!("Unable to read ids from {}, skipping the check: {}", table, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    path::Path,
+};
+use anyhow::{bail, ensure};
+use log::info;
+
+const HEADER_SIZE: usize = 20;
+
+const CHR_RACES_NAME: usize = 14;
+const CHR_CLASSES_NAME: usize = 4;
+const CHAR_TITLES_MASK_ID: usize = 36;
+const LOCALE_COUNT: usize = 16;
+
+const CHAR_SECTIONS_RACE: usize = 1;
+const CHAR_SECTIONS_SEX: usize = 2;
+const CHAR_SECTIONS_BASE_SECTION: usize = 3;
+const CHAR_SECTIONS_FLAGS: usize = 7;
+const CHAR_SECTIONS_VARIATION: usize = 8;
+const CHAR_SECTIONS_COLOR: usize = 9;
+
+const SECTION_SKIN: u32 = 0;
+const SECTION_FACE: u32 = 1;
+const SECTION_FACIAL_HAIR: u32 = 2;
+const SECTION_HAIR: u32 = 3;
+const SECTION_FLAG_PLAYER: u32 = 0x01;
+
+/// A client database file in the WDBC format.
+pub struct DbcFile {
+    field_count: usize,
+    record_size: usize,
+    records: Vec<u8>,
+    strings: Vec<u8>,
+}
+
+pub struct Record<'a> {
+    file: &'a DbcFile,
+    data: &'a [u8],
+}
+
+impl DbcFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        info!("Loading file {:?}", path.as_ref());
+        Self::parse(std::fs::read(path.as_ref())?)
+    }
+
+    pub fn parse(mut bytes: Vec<u8>) -> anyhow::Result<Self> {
+        ensure!(bytes.len() >= HEADER_SIZE, "dbc file is too short");
+        ensure!(&bytes[0..4] == b"WDBC", "not a dbc file");
+
+        let header = |index: usize| {
+            let offset = 4 + index * 4;
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let record_count = header(0);
+        let field_count = header(1);
+        let record_size = header(2);
+        let string_block_size = header(3);
+
+        let records_size = record_count * record_size;
+        ensure!(
+            bytes.len() == HEADER_SIZE + records_size + string_block_size,
+            "dbc file size does not match its header"
+        );
+
+        let strings = bytes.split_off(HEADER_SIZE + records_size);
+        let records = bytes.split_off(HEADER_SIZE);
+        Ok(Self {
+            field_count,
+            record_size,
+            records,
+            strings,
+        })
+    }
+
+    pub fn field_count(&self) -> usize {
+        self.field_count
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = Record<'_>> {
+        // chunks() panics on zero size even for an empty slice
+        self.records
+            .chunks(self.record_size.max(1))
+            .map(move |data| Record { file: self, data })
+    }
+}
+
+impl<'a> Record<'a> {
+    /// Reads a 4 byte field by its index.
+    pub fn u32(&self, field: usize) -> anyhow::Result<u32> {
+        let offset = field * 4;
+        match self.data.get(offset..offset + 4) {
+            Some(bytes) => Ok(u32::from_le_bytes(bytes.try_into().unwrap())),
+            None => bail!("field {} is out of record bounds", field),
+        }
+    }
+
+    /// Reads a single byte at the given byte offset, for files with packed fields.
+    pub fn u8(&self, offset: usize) -> anyhow::Result<u8> {
+        match self.data.get(offset) {
+            Some(byte) => Ok(*byte),
+            None => bail!("offset {} is out of record bounds", offset),
+        }
+    }
+
+    pub fn string(&self, field: usize) -> anyhow::Result<&'a str> {
+        let start = self.u32(field)? as usize;
+        let strings = &self.file.strings;
+        ensure!(start <= strings.len(), "string offset {} is out of bounds", start);
+        let end = strings[start..]
+            .iter()
+            .position(|b| *b == 0)
+            .map(|len| start + len)
+            .unwrap_or(strings.len());
+        Ok(std::str::from_utf8(&strings[start..end])?)
+    }
+
+    /// Returns the first non-empty string of a localized field.
+    pub fn localized(&self, field: usize) -> anyhow::Result<&'a str> {
+        for locale in 0..LOCALE_COUNT {
+            let value = self.string(field + locale)?;
+            if !value.is_empty() {
+                return Ok(value);
+            }
+        }
+        Ok("")
+    }
+}
+
+/// Appearance options available to one race and gender.
+#[derive(Debug, Default, Clone)]
+pub struct AppearanceOptions {
+    pub skin_colors: HashSet<u8>,
+    /// Pairs of face and skin color.
+    pub faces: HashSet<(u8, u8)>,
+    /// Pairs of hair style and hair color.
+    pub hair: HashSet<(u8, u8)>,
+    pub facial_hair: HashSet<u8>,
+}
+
+impl AppearanceOptions {
+    pub fn allows(&self, skin: u8, face: u8, hair_style: u8, hair_color: u8, facial_hair: u8) -> bool {
+        self.skin_colors.contains(&skin)
+            && self.faces.contains(&(face, skin))
+            && self.hair.contains(&(hair_style, hair_color))
+            && (facial_hair == 0 || self.facial_hair.contains(&facial_hair))
+    }
+}
+
+/// Character creation data read from the client's DBC files.
+#[derive(Debug, Default, Clone)]
+pub struct ClientData {
+    pub races: HashMap<u8, String>,
+    pub classes: HashMap<u8, String>,
+    pub combos: HashSet<(u8, u8)>,
+    pub appearance: HashMap<(u8, bool), AppearanceOptions>,
+    /// Bit of each title in the `knownTitles` mask, empty without CharTitles.dbc.
+    pub title_bits: HashMap<u32, u32>,
+}
+
+impl ClientData {
+    pub fn load<P: AsRef<Path>>(dir_path: P) -> anyhow::Result<Self> {
+        let dir_path = dir_path.as_ref();
+        let mut data = Self::default();
+
+        for record in DbcFile::open(dir_path.join("ChrRaces.dbc"))?.records() {
+            let name = record.localized(CHR_RACES_NAME)?.to_owned();
+            data.races.insert(record.u32(0)? as u8, name);
+        }
+        for record in DbcFile::open(dir_path.join("ChrClasses.dbc"))?.records() {
+            let name = record.localized(CHR_CLASSES_NAME)?.to_owned();
+            data.classes.insert(record.u32(0)? as u8, name);
+        }
+        for record in DbcFile::open(dir_path.join("CharBaseInfo.dbc"))?.records() {
+            data.combos.insert((record.u8(0)?, record.u8(1)?));
+        }
+        let titles_path = dir_path.join("CharTitles.dbc");
+        if titles_path.exists() {
+            for record in DbcFile::open(titles_path)?.records() {
+                data.title_bits.insert(record.u32(0)?, record.u32(CHAR_TITLES_MASK_ID)?);
+            }
+        }
+        for record in DbcFile::open(dir_path.join("CharSections.dbc"))?.records() {
+            if record.u32(CHAR_SECTIONS_FLAGS)? & SECTION_FLAG_PLAYER == 0 {
+                continue;
+            }
+            let race = record.u32(CHAR_SECTIONS_RACE)? as u8;
+            let female = record.u32(CHAR_SECTIONS_SEX)? != 0;
+            let variation = record.u32(CHAR_SECTIONS_VARIATION)? as u8;
+            let color = record.u32(CHAR_SECTIONS_COLOR)? as u8;
+            let entry = data.appearance.entry((race, female)).or_default();
+            match record.u32(CHAR_SECTIONS_BASE_SECTION)? {
+                SECTION_SKIN => {
+                    entry.skin_colors.insert(color);
+                }
+                SECTION_FACE => {
+                    entry.faces.insert((variation, color));
+                }
+                SECTION_FACIAL_HAIR => {
+                    entry.facial_hair.insert(variation);
+                }
+                SECTION_HAIR => {
+                    entry.hair.insert((variation, color));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(data)
+    }
+
+    pub fn can_create(&self, race: u8, class: u8) -> bool {
+        self.combos.contains(&(race, class))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fixtures() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/dbc")
+    }
+
+    #[test]
+    fn parse_header() {
+        assert!(DbcFile::parse(b"WDBC".to_vec()).is_err());
+        assert!(DbcFile::parse(b"WDB2\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".to_vec()).is_err());
+
+        let empty = DbcFile::parse(b"WDBC\0\0\0\0\x02\0\0\0\x08\0\0\0\x01\0\0\0\0".to_vec()).unwrap();
+        assert_eq!(empty.field_count(), 2);
+        assert_eq!(empty.records().count(), 0);
+    }
+
+    #[test]
+    fn load_client_data() {
+        let data = ClientData::load(fixtures()).unwrap();
+
+        assert_eq!(data.races.get(&1).map(String::as_str), Some("Human"));
+        assert_eq!(data.races.get(&2).map(String::as_str), Some("Orc"));
+        assert_eq!(data.classes.get(&1).map(String::as_str), Some("Warrior"));
+        assert_eq!(data.classes.get(&8).map(String::as_str), Some("Mage"));
+
+        assert!(data.can_create(1, 1));
+        assert!(data.can_create(1, 8));
+        assert!(data.can_create(2, 1));
+        assert!(!data.can_create(2, 8));
+
+        let human_male = &data.appearance[&(1, false)];
+        assert!(human_male.skin_colors.contains(&0));
+        assert!(human_male.skin_colors.contains(&1));
+        assert!(human_male.faces.contains(&(2, 1)));
+        assert!(human_male.hair.contains(&(3, 4)));
+        assert!(human_male.facial_hair.contains(&1));
+        assert!(human_male.allows(1, 2, 3, 4, 1));
+        assert!(human_male.allows(1, 2, 3, 4, 0));
+        assert!(!human_male.allows(0, 2, 3, 4, 0));
+        assert!(!human_male.allows(1, 2, 3, 5, 0));
+        // npc-only sections are ignored
+        assert!(!human_male.skin_colors.contains(&9));
+        assert!(!data.appearance.contains_key(&(2, true)));
+
+        assert_eq!(data.title_bits.get(&1), Some(&1));
+        assert_eq!(data.title_bits.get(&42), Some(&7));
+        assert_eq!(data.title_bits.get(&7), None);
+    }
+}
@@ -10,8 +10,20 @@ pub enum AppError {
     NotFound,
     #[error("resource already exists")]
     Conflict,
+    #[error("authentication required")]
+    Unauthorized,
+    #[error("access denied")]
+    Forbidden,
+    #[error("realm is online")]
+    RealmOnline,
+    #[error("account is banned")]
+    Banned(Option<u32>),
+    #[error("too many requests, retry in {0}s")]
+    TooManyRequests(u64),
     #[error("invalid request input: {0}")]
     InvalidInput(&'static str),
+    #[error("invalid request body: {0}")]
+    InvalidBody(String),
     #[error("database adapter error")]
     DatabaseError(sqlx::Error),
     #[error("unknown error")]
@@ -45,86 +57,152 @@ pub type AppResult<T> = Result<T, AppError>;
 impl Reject for AppError {}
 
 pub async fn handle_rejection(rej: Rejection) -> Result<impl Reply, Infallible> {
+    let mut response = describe_rejection(&rej).into_response();
+    match rej.find::<AppError>() {
+        Some(AppError::Unauthorized) => {
+            response.headers_mut().insert(
+                http::header::WWW_AUTHENTICATE,
+                http::HeaderValue::from_static("Basic realm=\"terra\""),
+            );
+        }
+        Some(AppError::TooManyRequests(seconds)) => {
+            response
+                .headers_mut()
+                .insert(http::header::RETRY_AFTER, http::HeaderValue::from(*seconds));
+        }
+        _ => {}
+    }
+    Ok(response)
+}
+
+fn describe_rejection(rej: &Rejection) -> impl Reply {
     if rej.is_not_found() {
-        Ok(warp::reply::with_status(
+        warp::reply::with_status(
             warp::reply::json(&json!({ "error": "not_found" })),
             StatusCode::NOT_FOUND,
-        ))
+        )
     } else if let Some(err) = rej.find::<AppError>() {
         match err {
-            AppError::NotFound => Ok(warp::reply::with_status(
+            AppError::NotFound => warp::reply::with_status(
                 warp::reply::json(&json!({
                     "error": "not_found",
                 })),
                 StatusCode::NOT_FOUND,
-            )),
-            AppError::Conflict => Ok(warp::reply::with_status(
+            ),
+            AppError::Conflict => warp::reply::with_status(
                 warp::reply::json(&json!({
                     "error": "conflict",
                 })),
                 StatusCode::CONFLICT,
-            )),
-            AppError::InvalidInput(reason) => Ok(warp::reply::with_status(
+            ),
+            AppError::Unauthorized => warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "error": "unauthorized",
+                })),
+                StatusCode::UNAUTHORIZED,
+            ),
+            AppError::Forbidden => warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "error": "forbidden",
+                })),
+                StatusCode::FORBIDDEN,
+            ),
+            AppError::RealmOnline => warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "error": "realm_online",
+                })),
+                StatusCode::CONFLICT,
+            ),
+            AppError::Banned(until) => warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "error": "account_banned",
+                    "until": until,
+                })),
+                StatusCode::FORBIDDEN,
+            ),
+            AppError::TooManyRequests(seconds) => warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "error": "too_many_requests",
+                    "retry_after": seconds,
+                })),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            AppError::InvalidInput(reason) => warp::reply::with_status(
                 warp::reply::json(&json!({
                     "error": "bad_request",
                     "cause": reason,
                 })),
                 StatusCode::BAD_REQUEST,
-            )),
-            AppError::DatabaseError(inner) => Ok(warp::reply::with_status(
+            ),
+            AppError::InvalidBody(cause) => warp::reply::with_status(
+                warp::reply::json(&json!({
+                    "error": "bad_request",
+                    "cause": cause,
+                })),
+                StatusCode::BAD_REQUEST,
+            ),
+            AppError::DatabaseError(inner) => warp::reply::with_status(
                 warp::reply::json(&json!({
                     "error": "internal_server_error",
                     "cause": format!("{:?}", inner),
                 })),
                 StatusCode::INTERNAL_SERVER_ERROR,
-            )),
-            AppError::Other(inner) => Ok(warp::reply::with_status(
+            ),
+            AppError::Other(inner) => warp::reply::with_status(
                 warp::reply::json(&json!({
                     "error": "internal_server_error",
                     "cause": format!("{:?}", inner),
                 })),
                 StatusCode::INTERNAL_SERVER_ERROR,
-            )),
+            ),
         }
     } else if let Some(err) = rej.find::<warp::reject::InvalidHeader>() {
-        Ok(warp::reply::with_status(
+        warp::reply::with_status(
             warp::reply::json(&json!({
                 "error": "bad_request",
                 "cause": err.name(),
             })),
             StatusCode::BAD_REQUEST,
-        ))
+        )
     } else if let Some(err) = rej.find::<warp::reject::MissingCookie>() {
-        Ok(warp::reply::with_status(
+        warp::reply::with_status(
             warp::reply::json(&json!({
                 "error": "bad_request",
                 "cause": err.name(),
             })),
             StatusCode::BAD_REQUEST,
-        ))
+        )
     } else if let Some(err) = rej.find::<warp::reject::MissingHeader>() {
-        Ok(warp::reply::with_status(
+        warp::reply::with_status(
             warp::reply::json(&json!({
                 "error": "bad_request",
                 "cause": err.name(),
             })),
             StatusCode::BAD_REQUEST,
-        ))
+        )
+    } else if let Some(err) = rej.find::<warp::reject::InvalidQuery>() {
+        warp::reply::with_status(
+            warp::reply::json(&json!({
+                "error": "bad_request",
+                "cause": err.to_string(),
+            })),
+            StatusCode::BAD_REQUEST,
+        )
     } else if let Some(err) = rej.find::<warp::filters::body::BodyDeserializeError>() {
-        Ok(warp::reply::with_status(
+        warp::reply::with_status(
             warp::reply::json(&json!({
                 "error": "bad_request",
                 "cause": err.to_string(),
             })),
             StatusCode::BAD_REQUEST,
-        ))
+        )
     } else {
-        Ok(warp::reply::with_status(
+        warp::reply::with_status(
             warp::reply::json(&json!({
                 "error": "internal_server_error",
                 "cause": format!("{:?}", rej),
             })),
             StatusCode::INTERNAL_SERVER_ERROR,
-        ))
+        )
     }
 }
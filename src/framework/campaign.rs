@@ -1,8 +1,10 @@
 use std::{collections::HashMap, num::NonZeroU32};
 use serde::{Deserialize, Serialize};
 use super::{
+    catalog::Contents,
     system::{Mods, System, SystemView},
     tags::Tags,
+    visibility::Visibility,
 };
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize)]
@@ -21,6 +23,7 @@ pub struct Role {
     pub limit: Option<NonZeroU32>,
     pub provides: Tags,
     pub mods: Mods,
+    pub contents: Contents,
 }
 
 #[derive(Debug, Serialize)]
@@ -31,12 +34,23 @@ pub struct Block {
     pub roles: Vec<String>,
 }
 
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Editing {
+    /// Biography edits of locked characters wait for a GM before they are applied.
+    #[serde(default)] pub reapprove_locked: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct Campaign {
+    /// Content hash of the campaign files, saved with each new character.
+    pub version: String,
     pub name: String,
     pub info: String,
     pub system: System,
     pub system_view: SystemView,
     pub blocks: Vec<Block>,
     pub roles: HashMap<String, Role>,
+    pub visibility: Visibility,
+    pub editing: Editing,
 }
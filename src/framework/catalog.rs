@@ -0,0 +1,182 @@
+use std::{collections::HashMap, num::NonZeroU32, path::Path};
+use serde::Serialize;
+use crate::util;
+use super::system::Mods;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ItemInfo {
+    pub name: String,
+    pub quality: u8,
+    pub slot: u8,
+    pub icon: Option<String>,
+    /// Most items of the kind that fit in one slot, unknown unless exported.
+    #[serde(skip)] pub stack: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SpellInfo {
+    pub name: String,
+    pub icon: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkillInfo {
+    pub name: String,
+}
+
+/// Human readable names for game ids, used to describe what an entity contains.
+#[derive(Debug, Default, Clone)]
+pub struct Catalog {
+    pub items: HashMap<u32, ItemInfo>,
+    pub spells: HashMap<u32, SpellInfo>,
+    pub skills: HashMap<u32, SkillInfo>,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Contents {
+    pub equipment: Vec<ContentsEntry>,
+    pub items: Vec<ContentsEntry>,
+    pub spells: Vec<ContentsEntry>,
+    pub skills: Vec<ContentsEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentsEntry {
+    pub id: NonZeroU32,
+    #[serde(skip_serializing_if = "Option::is_none")] pub slot: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub value: Option<i32>,
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub quality: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")] pub icon: Option<String>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads `items.csv`, `spells.csv` and `skills.csv` from a directory of
+    /// client data exports. Missing files are skipped, as are the optional
+    /// `icon` and `stack` columns.
+    pub fn load_csv<P: AsRef<Path>>(dir_path: P) -> anyhow::Result<Self> {
+        fn field<'a>(row: &'a HashMap<String, String>, name: &str) -> anyhow::Result<&'a str> {
+            row.get(name)
+                .map(String::as_str)
+                .ok_or_else(|| anyhow::anyhow!("missing column {:?}", name))
+        }
+        fn optional(row: &HashMap<String, String>, name: &str) -> Option<String> {
+            row.get(name).filter(|s| !s.is_empty()).cloned()
+        }
+
+        let dir_path = dir_path.as_ref();
+        let mut catalog = Self::new();
+
+        let items_path = dir_path.join("items.csv");
+        if items_path.exists() {
+            for row in util::load_csv(&items_path)? {
+                catalog.items.insert(field(&row, "id")?.parse()?, ItemInfo {
+                    name: field(&row, "name")?.to_owned(),
+                    quality: field(&row, "quality")?.parse()?,
+                    slot: field(&row, "slot")?.parse()?,
+                    icon: optional(&row, "icon"),
+                    stack: optional(&row, "stack").map(|s| s.parse()).transpose()?,
+                });
+            }
+        }
+        let spells_path = dir_path.join("spells.csv");
+        if spells_path.exists() {
+            for row in util::load_csv(&spells_path)? {
+                catalog.spells.insert(field(&row, "id")?.parse()?, SpellInfo {
+                    name: field(&row, "name")?.to_owned(),
+                    icon: optional(&row, "icon"),
+                });
+            }
+        }
+        let skills_path = dir_path.join("skills.csv");
+        if skills_path.exists() {
+            for row in util::load_csv(&skills_path)? {
+                catalog.skills.insert(field(&row, "id")?.parse()?, SkillInfo {
+                    name: field(&row, "name")?.to_owned(),
+                });
+            }
+        }
+
+        Ok(catalog)
+    }
+
+    pub fn merge_in(&mut self, other: &Self) {
+        for (id, info) in &other.items {
+            let mine = self.items.entry(*id).or_insert_with(|| info.clone());
+            mine.stack = mine.stack.or(info.stack);
+        }
+        for (id, info) in &other.spells {
+            self.spells.entry(*id).or_insert_with(|| info.clone());
+        }
+        for (id, info) in &other.skills {
+            self.skills.entry(*id).or_insert_with(|| info.clone());
+        }
+    }
+
+    pub fn item(&self, id: NonZeroU32, slot: Option<&'static str>, count: Option<i32>) -> ContentsEntry {
+        let info = self.items.get(&id.get());
+        ContentsEntry {
+            id,
+            slot,
+            value: count,
+            name: info.map(|i| i.name.clone()),
+            quality: info.map(|i| i.quality),
+            icon: info.and_then(|i| i.icon.clone()),
+        }
+    }
+
+    pub fn spell(&self, id: NonZeroU32) -> ContentsEntry {
+        let info = self.spells.get(&id.get());
+        ContentsEntry {
+            id,
+            slot: None,
+            value: None,
+            name: info.map(|i| i.name.clone()),
+            quality: None,
+            icon: info.and_then(|i| i.icon.clone()),
+        }
+    }
+
+    pub fn skill(&self, id: NonZeroU32, value: i32) -> ContentsEntry {
+        ContentsEntry {
+            id,
+            slot: None,
+            value: Some(value),
+            name: self.skills.get(&id.get()).map(|i| i.name.clone()),
+            quality: None,
+            icon: None,
+        }
+    }
+
+    pub fn contents(
+        &self,
+        mods: &Mods,
+        equipment: impl IntoIterator<Item = (&'static str, NonZeroU32)>,
+    ) -> Contents {
+        let mut contents = Contents {
+            equipment: equipment
+                .into_iter()
+                .map(|(slot, id)| self.item(id, Some(slot), None))
+                .collect(),
+            items: mods
+                .items
+                .iter()
+                .map(|(id, count)| self.item(*id, None, Some(*count)))
+                .collect(),
+            spells: mods.spells.iter().map(|id| self.spell(*id)).collect(),
+            skills: mods
+                .skills
+                .iter()
+                .map(|(id, value)| self.skill(*id, *value))
+                .collect(),
+        };
+        contents.items.sort_by_key(|e| e.id);
+        contents.spells.sort_by_key(|e| e.id);
+        contents.skills.sort_by_key(|e| e.id);
+        contents
+    }
+}
@@ -1,20 +1,34 @@
 pub mod campaign;
+pub mod catalog;
+pub mod profile;
 pub mod system;
 pub mod tags;
+pub mod visibility;
 
-use std::{collections::HashMap, num::NonZeroU32, path::Path};
-use log::{debug, trace, warn};
+use std::{
+    collections::HashMap,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+use log::{debug, info, trace, warn};
+use ring::digest;
 use serde::Deserialize;
-use crate::util;
+use anyhow::bail;
+use crate::{db::world::WorldIndex, dbc::ClientData, util};
 use self::{
-    campaign::{Block, Campaign, Role, RoleKind},
+    campaign::{Block, Campaign, Editing, Role, RoleKind},
+    catalog::Catalog,
     system::{Mods, System},
     tags::Tags,
+    visibility::Visibility,
 };
 
 pub fn load_campaign<P: AsRef<Path>>(
     campaign_path: P,
     assets_path: Option<P>,
+    world: Option<&WorldIndex>,
+    catalog: &Catalog,
+    client: Option<&ClientData>,
 ) -> anyhow::Result<Campaign> {
     #[derive(Deserialize)]
     #[serde(deny_unknown_fields)]
@@ -22,6 +36,10 @@ pub fn load_campaign<P: AsRef<Path>>(
         name: String,
         role_template: RoleTemplate,
         blocks: Vec<BlockDef>,
+        #[serde(default)]
+        visibility: Visibility,
+        #[serde(default)]
+        editing: Editing,
     }
     #[derive(Clone, Deserialize)]
     #[serde(deny_unknown_fields)]
@@ -66,6 +84,8 @@ pub fn load_campaign<P: AsRef<Path>>(
 
     let campaign_path = campaign_path.as_ref();
 
+    let version = content_version(campaign_path)?;
+    info!("Campaign version is {}", version);
     let manifest: ManifestFile = util::load_yaml(&campaign_path.join("manifest.yml"))?;
     let info = util::load_markdown(&campaign_path.join("info.md"))?;
     let system = load_system(&[
@@ -85,6 +105,10 @@ pub fn load_campaign<P: AsRef<Path>>(
         }
     }
 
+    if let Some(client) = client {
+        check_client_ids(&system, client)?;
+    }
+
     let mut resolved_blocks = Vec::new();
     let mut resolved_roles = HashMap::new();
 
@@ -108,6 +132,7 @@ pub fn load_campaign<P: AsRef<Path>>(
                 limit: role.limit,
                 provides: role.provides,
                 mods: role.mods,
+                contents: Default::default(),
             };
             compiled_role.mods.merge_in(&manifest.role_template.mods);
             compiled_role.mods.merge_in(&block.mods);
@@ -119,22 +144,183 @@ pub fn load_campaign<P: AsRef<Path>>(
                 .provides
                 .add(format!("block/{}", &compiled_block.id), 1);
             compiled_role.provides.add(format!("role/{}", &id), 1);
+            compiled_role.contents = catalog.contents(&compiled_role.mods, Vec::new());
             compiled_block.roles.push(id.clone());
             resolved_roles.insert(id, compiled_role);
         }
         resolved_blocks.push(compiled_block);
     }
 
+    if let Some(world) = world {
+        check_world_ids(&system, &resolved_roles, world);
+    }
+
     Ok(Campaign {
+        version,
         name: manifest.name,
         info,
-        system_view: system.view(),
+        system_view: system.view(catalog),
         system,
         blocks: resolved_blocks,
         roles: resolved_roles,
+        visibility: manifest.visibility,
+        editing: manifest.editing,
     })
 }
 
+/// Hash over the files the campaign is loaded from, changing with any edit of them.
+fn content_version(campaign_path: &Path) -> anyhow::Result<String> {
+    fn collect(path: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                collect(&entry?.path(), files)?;
+            }
+        } else if path.exists() {
+            files.push(path.to_owned());
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    for name in &["manifest.yml", "info.md", "system.yml", "system"] {
+        collect(&campaign_path.join(name), &mut files)?;
+    }
+    files.sort();
+
+    let mut context = digest::Context::new(&digest::SHA256);
+    for file in &files {
+        let name = file.strip_prefix(campaign_path).unwrap_or(file);
+        context.update(name.to_string_lossy().as_bytes());
+        context.update(&[0]);
+        let content = std::fs::read(file)?;
+        context.update(&(content.len() as u64).to_le_bytes());
+        context.update(&content);
+    }
+    // shortened, it only has to tell revisions apart
+    let hash = util::hexstring(context.finish());
+    Ok(hash[..16].to_owned())
+}
+
+fn check_client_ids(system: &System, client: &ClientData) -> anyhow::Result<()> {
+    for (id, race) in &system.race {
+        if !client.races.contains_key(&race.game_id) {
+            bail!("race/{} has game_id {} unknown to the client", id, race.game_id);
+        }
+    }
+    for (id, class) in &system.class {
+        if !client.classes.contains_key(&class.game_id) {
+            bail!("class/{} has game_id {} unknown to the client", id, class.game_id);
+        }
+    }
+    // conditions may already keep such pairs apart, creation rejects them either way
+    for (race_id, race) in &system.race {
+        for (class_id, class) in &system.class {
+            if !client.can_create(race.game_id, class.game_id) {
+                warn!("Client cannot create race/{} with class/{}", race_id, class_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_world_ids(system: &System, roles: &HashMap<String, Role>, world: &WorldIndex) {
+    // allowed InventoryType values from item_template
+    fn allowed_types(slot: &str) -> &'static [u8] {
+        match slot {
+            "head" => &[1],
+            "neck" => &[2],
+            "shoulders" => &[3],
+            "body" => &[4],
+            "chest" => &[5, 20],
+            "waist" => &[6],
+            "legs" => &[7],
+            "feet" => &[8],
+            "wrists" => &[9],
+            "hands" => &[10],
+            "finger" => &[11],
+            "trinket" => &[12],
+            "back" => &[16],
+            "tabard" => &[19],
+            "bag" => &[18, 27],
+            "mainhand" => &[13, 17, 21],
+            "offhand" => &[13, 14, 17, 22, 23],
+            "ranged" => &[15, 25, 26, 28],
+            _ => &[],
+        }
+    }
+
+    let check_item = |source: &str, id: NonZeroU32, slot: Option<&str>| {
+        match world.items.get(&id.get()) {
+            None => warn!("Unknown item {} in {}", id, source),
+            Some(info) => match slot {
+                Some(slot) if !allowed_types(slot).contains(&info.slot) => warn!(
+                    "Item {} in {} does not fit {} slot (inventory type {})",
+                    id, source, slot, info.slot
+                ),
+                _ => trace!("Found item {} in {}", id, source),
+            },
+        }
+    };
+    let check_mods = |source: &str, mods: &Mods| {
+        for id in mods.items.keys() {
+            check_item(source, *id, None);
+        }
+        if let Some(spells) = &world.spells {
+            for id in mods.spells.iter().chain(&mods.spells_banned) {
+                if !spells.contains(&id.get()) {
+                    warn!("Unknown spell {} in {}", id, source);
+                }
+            }
+        }
+        if let Some(skills) = &world.skills {
+            for id in mods.skills.keys() {
+                if !skills.contains(&id.get()) {
+                    warn!("Unknown skill {} in {}", id, source);
+                }
+            }
+        }
+    };
+
+    for (id, role) in roles {
+        check_mods(&format!("role/{}", id), &role.mods);
+    }
+    for (id, race) in &system.race {
+        check_mods(&format!("race/{}", id), &race.mods);
+    }
+    for (id, class) in &system.class {
+        check_mods(&format!("class/{}", id), &class.mods);
+    }
+    for (id, t) in &system.traits {
+        check_mods(&format!("trait/{}", id), &t.mods);
+    }
+    for (id, location) in &system.location {
+        check_mods(&format!("location/{}", id), &location.mods);
+    }
+    for (id, armor) in &system.armor {
+        let source = format!("armor/{}", id);
+        check_mods(&source, &armor.mods);
+        for (slot, id) in armor.equipment() {
+            check_item(&source, id, Some(slot));
+        }
+        for (name, ids, limit) in &[
+            ("fingers", &armor.fingers, 2),
+            ("trinkets", &armor.trinkets, 2),
+            ("bags", &armor.bags, 4),
+        ] {
+            if ids.len() > *limit {
+                warn!("Too many {} in {}, only {} will be equipped", name, source, limit);
+            }
+        }
+    }
+    for (id, weapon) in &system.weapon {
+        let source = format!("weapon/{}", id);
+        check_mods(&source, &weapon.mods);
+        for (slot, id) in weapon.equipment() {
+            check_item(&source, id, Some(slot));
+        }
+    }
+}
+
 pub fn load_system<I>(paths: I) -> anyhow::Result<System>
 where
     I: IntoIterator,
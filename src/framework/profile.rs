@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use crate::util;
+use super::{campaign::Campaign, system::EntityView};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntityRef {
+    pub id: String,
+    pub name: String,
+    pub preview: Option<String>,
+}
+
+/// Display data of a character resolved against the campaign.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Profile {
+    pub biography: Option<String>,
+    pub block: Option<EntityRef>,
+    pub role: Option<EntityRef>,
+    pub race: Option<EntityRef>,
+    pub class: Option<EntityRef>,
+    pub location: Option<EntityRef>,
+    pub armor: Option<EntityRef>,
+    pub weapon: Option<EntityRef>,
+    pub traits: Vec<EntityRef>,
+}
+
+impl Profile {
+    /// Builds a profile from character metadata. Ids the campaign no longer
+    /// knows are left out.
+    pub fn new(campaign: &Campaign, female: bool, metadata: &JsonValue) -> Self {
+        let view = &campaign.system_view;
+        let field = |name: &str| metadata.get(name).and_then(JsonValue::as_str);
+        let resolve = |entries: &HashMap<String, EntityView>, id: &str| {
+            entries.get(id).map(|entry| EntityRef {
+                id: id.to_owned(),
+                name: match (&entry.meta.name_female, female) {
+                    (Some(name), true) => name.clone(),
+                    _ => entry.meta.name.clone(),
+                },
+                preview: entry.meta.preview.clone(),
+            })
+        };
+
+        let role_id = field("role");
+        let role = role_id.and_then(|id| {
+            campaign.roles.get(id).map(|role| EntityRef {
+                id: id.to_owned(),
+                name: role.name.clone(),
+                preview: None,
+            })
+        });
+        let block = role_id.and_then(|id| {
+            campaign
+                .blocks
+                .iter()
+                .find(|block| block.roles.iter().any(|r| r == id))
+                .map(|block| EntityRef {
+                    id: block.id.clone(),
+                    name: block.name.clone(),
+                    preview: None,
+                })
+        });
+
+        Self {
+            biography: field("info").map(util::render_markdown),
+            block,
+            role,
+            race: field("race").and_then(|id| resolve(&view.race, id)),
+            class: field("class").and_then(|id| resolve(&view.class, id)),
+            location: field("location").and_then(|id| resolve(&view.location, id)),
+            armor: field("armor").and_then(|id| resolve(&view.armor, id)),
+            weapon: field("weapon").and_then(|id| resolve(&view.weapon, id)),
+            traits: metadata
+                .get("traits")
+                .and_then(JsonValue::as_array)
+                .map(|ids| {
+                    ids.iter()
+                        .filter_map(JsonValue::as_str)
+                        .filter_map(|id| resolve(&view.traits, id))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
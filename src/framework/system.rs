@@ -4,7 +4,10 @@ use std::{
     num::NonZeroU32,
 };
 use serde::{Deserialize, Serialize};
-use super::tags::{Condition, Tags};
+use super::{
+    catalog::{Catalog, Contents},
+    tags::{Condition, Tags},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -27,6 +30,49 @@ pub struct Mods {
     #[serde(default)] pub items: HashMap<NonZeroU32, i32>,
     #[serde(default)] pub money: i32,
     #[serde(default)] pub level: i32,
+    #[serde(default)] pub reputations: HashMap<NonZeroU32, i32>,
+    #[serde(default)] pub titles: HashSet<NonZeroU32>,
+    #[serde(default)] pub quests: HashSet<NonZeroU32>,
+    #[serde(default)] pub taxi_nodes: HashSet<NonZeroU32>,
+    #[serde(default)] pub homebind: Option<Homebind>,
+    #[serde(default)] pub appearance: Option<Appearance>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Homebind {
+    pub map: u32,
+    pub zone: u32,
+    pub position: (f32, f32, f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Appearance {
+    pub skin: u8,
+    pub face: u8,
+    pub hair_style: u8,
+    pub hair_color: u8,
+    pub facial_hair: u8,
+}
+
+impl Appearance {
+    /// Checks every option against the highest allowed value in `limits`.
+    pub fn fits(&self, limits: &Appearance) -> bool {
+        self.skin <= limits.skin
+            && self.face <= limits.face
+            && self.hair_style <= limits.hair_style
+            && self.hair_color <= limits.hair_color
+            && self.facial_hair <= limits.facial_hair
+    }
+}
+
+/// Appearance limits of a race when no client data is available.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Customization {
+    #[serde(default)] pub male: Option<Appearance>,
+    #[serde(default)] pub female: Option<Appearance>,
 }
 
 impl Mods {
@@ -51,6 +97,17 @@ impl Mods {
         sum(&mut self.items, &other.items);
         self.money += other.money;
         self.level += other.level;
+        sum(&mut self.reputations, &other.reputations);
+        self.titles.extend(&other.titles);
+        self.quests.extend(&other.quests);
+        self.taxi_nodes.extend(&other.taxi_nodes);
+        // the first defined homebind and appearance win, same as with system entries
+        if self.homebind.is_none() {
+            self.homebind = other.homebind;
+        }
+        if self.appearance.is_none() {
+            self.appearance = other.appearance;
+        }
     }
 }
 
@@ -59,6 +116,7 @@ impl Mods {
 pub struct Race {
     #[serde(flatten)] pub meta: Metadata,
     pub game_id: u8,
+    #[serde(default)] pub customization: Option<Customization>,
     #[serde(flatten)] pub mods: Mods,
 }
 
@@ -120,6 +178,46 @@ pub struct Location {
     #[serde(flatten)] pub mods: Mods,
 }
 
+impl Armor {
+    pub fn equipment(&self) -> Vec<(&'static str, NonZeroU32)> {
+        let single = [
+            ("head", self.head),
+            ("neck", self.neck),
+            ("shoulders", self.shoulders),
+            ("body", self.body),
+            ("chest", self.chest),
+            ("waist", self.waist),
+            ("legs", self.legs),
+            ("feet", self.feet),
+            ("wrists", self.wrists),
+            ("hands", self.hands),
+            ("back", self.back),
+            ("tabard", self.tabard),
+        ];
+        single
+            .iter()
+            .filter_map(|(slot, maybe_id)| maybe_id.map(|id| (*slot, id)))
+            .chain(self.fingers.iter().map(|id| ("finger", *id)))
+            .chain(self.trinkets.iter().map(|id| ("trinket", *id)))
+            .chain(self.bags.iter().map(|id| ("bag", *id)))
+            .collect()
+    }
+}
+
+impl Weapon {
+    pub fn equipment(&self) -> Vec<(&'static str, NonZeroU32)> {
+        let single = [
+            ("mainhand", self.mainhand),
+            ("offhand", self.offhand),
+            ("ranged", self.ranged),
+        ];
+        single
+            .iter()
+            .filter_map(|(slot, maybe_id)| maybe_id.map(|id| (*slot, id)))
+            .collect()
+    }
+}
+
 impl AsRef<Metadata> for Race {
     fn as_ref(&self) -> &Metadata {
         &self.meta
@@ -187,8 +285,8 @@ impl System {
         walk(&mut self.location, &other.location);
     }
 
-    pub fn view(&self) -> SystemView {
-        SystemView::new(self)
+    pub fn view(&self, catalog: &Catalog) -> SystemView {
+        SystemView::new(self, catalog)
     }
 
     pub fn info_iter(&self) -> impl Iterator<Item = &Metadata> {
@@ -203,49 +301,61 @@ impl System {
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct EntityView {
+    #[serde(flatten)] pub meta: Metadata,
+    pub contents: Contents,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct SystemView {
-    pub race: HashMap<String, Metadata>,
-    pub class: HashMap<String, Metadata>,
-    pub armor: HashMap<String, Metadata>,
-    pub weapon: HashMap<String, Metadata>,
+    pub race: HashMap<String, EntityView>,
+    pub class: HashMap<String, EntityView>,
+    pub armor: HashMap<String, EntityView>,
+    pub weapon: HashMap<String, EntityView>,
     #[serde(rename = "trait")]
-    pub traits: HashMap<String, Metadata>,
-    pub location: HashMap<String, Metadata>,
+    pub traits: HashMap<String, EntityView>,
+    pub location: HashMap<String, EntityView>,
 }
 
 impl SystemView {
-    pub fn new(system: &System) -> Self {
+    pub fn new(system: &System, catalog: &Catalog) -> Self {
+        let view = |meta: &Metadata, mods: &Mods, equipment: Vec<(&'static str, NonZeroU32)>| {
+            EntityView {
+                meta: meta.clone(),
+                contents: catalog.contents(mods, equipment),
+            }
+        };
         Self {
             race: system
                 .race
                 .iter()
-                .map(|(id, data)| (id.clone(), data.meta.clone()))
+                .map(|(id, data)| (id.clone(), view(&data.meta, &data.mods, Vec::new())))
                 .collect(),
             class: system
                 .class
                 .iter()
-                .map(|(id, data)| (id.clone(), data.meta.clone()))
+                .map(|(id, data)| (id.clone(), view(&data.meta, &data.mods, Vec::new())))
                 .collect(),
             armor: system
                 .armor
                 .iter()
-                .map(|(id, data)| (id.clone(), data.meta.clone()))
+                .map(|(id, data)| (id.clone(), view(&data.meta, &data.mods, data.equipment())))
                 .collect(),
             weapon: system
                 .weapon
                 .iter()
-                .map(|(id, data)| (id.clone(), data.meta.clone()))
+                .map(|(id, data)| (id.clone(), view(&data.meta, &data.mods, data.equipment())))
                 .collect(),
             traits: system
                 .traits
                 .iter()
-                .map(|(id, data)| (id.clone(), data.meta.clone()))
+                .map(|(id, data)| (id.clone(), view(&data.meta, &data.mods, Vec::new())))
                 .collect(),
             location: system
                 .location
                 .iter()
-                .map(|(id, data)| (id.clone(), data.meta.clone()))
+                .map(|(id, data)| (id.clone(), view(&data.meta, &data.mods, Vec::new())))
                 .collect(),
         }
     }
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use super::{campaign::Campaign, tags::Tags};
+
+/// Who is looking at a character.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Audience {
+    Owner,
+    Other,
+    Gm,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Metadata fields removed entirely.
+    #[serde(default)] pub hide_fields: HashSet<String>,
+    /// Entries providing any of these tags are removed from metadata.
+    #[serde(default)] pub hide_tagged: HashSet<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Visibility {
+    #[serde(default)] pub owner: Rule,
+    #[serde(default)] pub other: Rule,
+    #[serde(default)] pub gm: Rule,
+}
+
+impl Rule {
+    /// Whether the id in a metadata field is hidden, by field or by its tags.
+    pub fn hides(&self, campaign: &Campaign, field: &str, id: &str) -> bool {
+        self.hide_fields.contains(field) || self.hides_tagged(lookup(campaign, field, id))
+    }
+
+    fn hides_tagged(&self, tags: Option<&Tags>) -> bool {
+        tags.map(|t| self.hide_tagged.iter().any(|tag| t.has(tag)))
+            .unwrap_or(false)
+    }
+}
+
+impl Visibility {
+    pub fn rule(&self, audience: Audience) -> &Rule {
+        match audience {
+            Audience::Owner => &self.owner,
+            Audience::Other => &self.other,
+            Audience::Gm => &self.gm,
+        }
+    }
+
+    /// Strips character metadata down to what the audience is allowed to see.
+    pub fn apply(&self, campaign: &Campaign, audience: Audience, metadata: &mut JsonValue) {
+        let rule = self.rule(audience);
+        let object = match metadata.as_object_mut() {
+            Some(object) => object,
+            None => return,
+        };
+
+        for field in &rule.hide_fields {
+            object.remove(field);
+        }
+        if rule.hide_tagged.is_empty() {
+            return;
+        }
+
+        let hidden = |field: &str, id: &str| rule.hides_tagged(lookup(campaign, field, id));
+        for (field, value) in object.iter_mut() {
+            let hide = match value {
+                JsonValue::String(id) => hidden(field, id),
+                JsonValue::Array(ids) => {
+                    ids.retain(|id| !id.as_str().map(|id| hidden(field, id)).unwrap_or(false));
+                    false
+                }
+                _ => false,
+            };
+            if hide {
+                *value = JsonValue::Null;
+            }
+        }
+    }
+}
+
+fn lookup<'a>(campaign: &'a Campaign, field: &str, id: &str) -> Option<&'a Tags> {
+    let system = &campaign.system;
+    match field {
+        "role" => campaign.roles.get(id).map(|e| &e.provides),
+        "race" => system.race.get(id).map(|e| &e.meta.provides),
+        "class" => system.class.get(id).map(|e| &e.meta.provides),
+        "armor" => system.armor.get(id).map(|e| &e.meta.provides),
+        "weapon" => system.weapon.get(id).map(|e| &e.meta.provides),
+        "traits" => system.traits.get(id).map(|e| &e.meta.provides),
+        "location" => system.location.get(id).map(|e| &e.meta.provides),
+        _ => None,
+    }
+}
@@ -1,46 +1,252 @@
-use std::{net::SocketAddr, path::PathBuf, sync::Arc};
-use log::info;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use log::{debug, error, info};
 use serde::Deserialize;
 use sqlx::mysql::MySqlPool;
-use crate::{db::DBConfig, framework, framework::campaign::Campaign};
+use crate::{
+    auth::{self, ApiKeyConfig, PermissionMap, RbacConfig, ReplayGuard},
+    db::{self, account::PasswordRules, world::{self, WorldDBConfig}, Backend, DBConfig},
+    dbc::ClientData,
+    error::{AppError, AppResult},
+    framework,
+    framework::{campaign::Campaign, catalog::Catalog},
+    limit::{RateLimiter, RateLimits},
+    mail::MailConfig,
+};
 
+fn default_realm_id() -> u32 { 1 }
+
+/// A realm terra manages characters for.
 #[derive(Deserialize)]
-pub struct AppConfig {
-    pub listen: SocketAddr,
+#[serde(deny_unknown_fields)]
+pub struct RealmConfig {
+    /// Id in `realmlist`.
+    pub id: u32,
+    pub chars_db: DBConfig,
+    #[serde(default)]
+    pub chars_backend: Backend,
     pub campaign_path: PathBuf,
     #[serde(default)]
     pub assets_path: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+pub struct AppConfig {
+    pub listen: SocketAddr,
     pub auth_db: DBConfig,
-    pub chars_db: DBConfig,
+    #[serde(default)]
+    pub realms: Vec<RealmConfig>,
+    /// A single realm can be configured at the top level instead of in `realms`.
+    #[serde(default)]
+    pub campaign_path: Option<PathBuf>,
+    #[serde(default)]
+    pub assets_path: Option<PathBuf>,
+    #[serde(default)]
+    pub chars_db: Option<DBConfig>,
+    #[serde(default = "default_realm_id")]
+    pub realm_id: u32,
+    #[serde(default)]
+    pub chars_backend: Backend,
+    #[serde(default)]
+    pub world_db: Option<WorldDBConfig>,
+    #[serde(default)]
+    pub catalog_path: Option<PathBuf>,
+    #[serde(default)]
+    pub dbc_path: Option<PathBuf>,
+    #[serde(default = "auth::default_permissions")]
+    pub permissions: PermissionMap,
+    #[serde(default)]
+    pub rbac: Option<RbacConfig>,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    #[serde(default)]
+    pub password_rules: PasswordRules,
+    #[serde(default)]
+    pub mail: Option<MailConfig>,
+    #[serde(default)]
+    pub require_invite: bool,
 }
 
-pub struct AppContext {
+impl AppConfig {
+    /// The configured realms, the top level one included.
+    fn take_realms(&mut self) -> anyhow::Result<Vec<RealmConfig>> {
+        let mut realms = std::mem::take(&mut self.realms);
+        match (self.chars_db.take(), self.campaign_path.take()) {
+            (Some(chars_db), Some(campaign_path)) => realms.insert(0, RealmConfig {
+                id: self.realm_id,
+                chars_db,
+                chars_backend: self.chars_backend,
+                campaign_path,
+                assets_path: self.assets_path.take(),
+            }),
+            (None, None) => {}
+            _ => anyhow::bail!("chars_db and campaign_path must be set together"),
+        }
+        if realms.is_empty() {
+            anyhow::bail!("no realms configured");
+        }
+        for (i, realm) in realms.iter().enumerate() {
+            if realms[..i].iter().any(|other| other.id == realm.id) {
+                anyhow::bail!("realm {} is configured twice", realm.id);
+            }
+        }
+        Ok(realms)
+    }
+}
+
+pub struct RealmContext {
+    pub id: u32,
     pub campaign: Campaign,
-    pub auth_db: MySqlPool,
     pub chars_db: MySqlPool,
+    pub chars_backend: Backend,
+}
+
+pub struct AppContext {
+    /// In configuration order, account level actions are audited in the first.
+    pub realms: Vec<RealmContext>,
+    pub auth_db: MySqlPool,
+    pub catalog: Catalog,
+    pub client_data: Option<ClientData>,
+    pub permissions: PermissionMap,
+    pub rbac: Option<RbacConfig>,
+    pub api_keys: Vec<ApiKeyConfig>,
+    pub replay_guard: ReplayGuard,
+    pub rate_limits: RateLimits,
+    pub rate_limiter: RateLimiter,
+    pub password_rules: PasswordRules,
+    pub mail: Option<MailConfig>,
+    pub require_invite: bool,
+}
+
+impl AppContext {
+    pub fn realm(&self, id: u32) -> AppResult<&RealmContext> {
+        self.realms
+            .iter()
+            .find(|realm| realm.id == id)
+            .ok_or(AppError::NotFound)
+    }
+
+    pub fn main_realm(&self) -> &RealmContext {
+        &self.realms[0]
+    }
 }
 
 pub type CtxRef = Arc<AppContext>;
 
-pub fn create_context(config: AppConfig) -> anyhow::Result<CtxRef> {
+const RENAME_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn create_context(mut config: AppConfig) -> anyhow::Result<CtxRef> {
+    let realm_configs = config.take_realms()?;
+
     info!(
         "Initializing auth database connection pool: {}",
         &config.auth_db.connect
     );
     let auth_db = futures::executor::block_on(config.auth_db.create_pool())?;
 
-    info!(
-        "Initializing characters database connection pool: {}",
-        &config.chars_db.connect
-    );
-    let chars_db = futures::executor::block_on(config.chars_db.create_pool())?;
+    let world_index = if let Some(world_config) = &config.world_db {
+        info!(
+            "Initializing world database connection pool: {}",
+            &world_config.db.connect
+        );
+        let world_db = futures::executor::block_on(world_config.db.create_pool())?;
+        info!("Indexing world database");
+        let world_index = futures::executor::block_on(world::load_index(&world_db, world_config));
+        // The index is all that is needed afterwards
+        futures::executor::block_on(world_db.close());
+        Some(world_index?)
+    } else {
+        None
+    };
+
+    let mut catalog = if let Some(catalog_path) = &config.catalog_path {
+        info!("Loading catalog exports: {:?}", catalog_path);
+        Catalog::load_csv(catalog_path)?
+    } else {
+        Catalog::new()
+    };
+    if let Some(world_index) = &world_index {
+        catalog.merge_in(&world_index.catalog());
+    }
 
-    info!("Loading campaign data: {:?}", &config.campaign_path);
-    let campaign = framework::load_campaign(config.campaign_path, config.assets_path)?;
+    let client_data = if let Some(dbc_path) = &config.dbc_path {
+        info!("Loading client data: {:?}", dbc_path);
+        Some(ClientData::load(dbc_path)?)
+    } else {
+        None
+    };
+
+    if config.rbac.is_some() {
+        info!("Permissions are read from RBAC tables");
+    }
+
+    if let Some(mail) = &config.mail {
+        info!("Sending mail through {}:{}", mail.host, mail.port);
+    }
+    if config.require_invite {
+        info!("New accounts need an invite code");
+    }
+    for key in &config.api_keys {
+        info!("Accepting API key {:?} with scopes {:?}", key.name, key.scopes);
+    }
+
+    let mut realms = Vec::new();
+    for realm in realm_configs {
+        info!(
+            "Initializing characters database connection pool of realm {}: {}",
+            realm.id, &realm.chars_db.connect
+        );
+        let chars_db = futures::executor::block_on(realm.chars_db.create_pool())?;
+        info!(
+            "Characters of realm {} are written for {:?} backend",
+            realm.id, realm.chars_backend
+        );
+        info!("Loading campaign data of realm {}: {:?}", realm.id, &realm.campaign_path);
+        let campaign = framework::load_campaign(
+            realm.campaign_path,
+            realm.assets_path,
+            world_index.as_ref(),
+            &catalog,
+            client_data.as_ref(),
+        )?;
+        realms.push(RealmContext {
+            id: realm.id,
+            campaign,
+            chars_db,
+            chars_backend: realm.chars_backend,
+        });
+    }
 
     Ok(Arc::new(AppContext {
-        campaign,
+        realms,
         auth_db,
-        chars_db,
+        catalog,
+        client_data,
+        permissions: config.permissions,
+        rbac: config.rbac,
+        api_keys: config.api_keys,
+        replay_guard: ReplayGuard::default(),
+        rate_limits: config.rate_limits,
+        rate_limiter: RateLimiter::default(),
+        password_rules: config.password_rules,
+        mail: config.mail,
+        require_invite: config.require_invite,
     }))
 }
+
+/// Periodically applies renames queued for characters that were online.
+pub async fn rename_task(ctx: CtxRef) {
+    let mut interval = tokio::time::interval(RENAME_INTERVAL);
+    loop {
+        interval.tick().await;
+        for realm in &ctx.realms {
+            match db::rename::apply_queued(realm.chars_db.clone()).await {
+                Ok(0) => {}
+                Ok(count) => info!("Applied {} queued renames on realm {}", count, realm.id),
+                Err(err) => error!("Unable to apply queued renames on realm {}: {}", realm.id, err),
+            }
+        }
+        debug!("Queued renames checked");
+    }
+}
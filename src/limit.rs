@@ -0,0 +1,402 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use http::HeaderMap;
+use serde::Deserialize;
+use crate::{
+    auth::Caller,
+    error::{AppError, AppResult},
+};
+
+/// Buckets are only dropped once the map gets this large.
+const MAX_BUCKETS: usize = 10_000;
+
+/// A token bucket holding up to `burst` requests, refilled at `per_hour`,
+/// which counts as at least one.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub burst: u32,
+    pub per_hour: u32,
+}
+
+impl BucketConfig {
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_hour.max(1)) / 3600.0
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteLimits {
+    #[serde(default)] pub per_ip: Option<BucketConfig>,
+    #[serde(default)] pub per_account: Option<BucketConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimits {
+    #[serde(default = "default_account_create")] pub account_create: RouteLimits,
+    #[serde(default = "default_character_create")] pub character_create: RouteLimits,
+    #[serde(default = "default_check_name")] pub check_name: RouteLimits,
+    #[serde(default = "default_password_reset")] pub password_reset: RouteLimits,
+    #[serde(default = "default_email_verify")] pub email_verify: RouteLimits,
+    /// Failed logins per client address and username, on every route.
+    #[serde(default = "default_failed_login")] pub failed_login: Option<BucketConfig>,
+    /// Header carrying the client address when terra runs behind a proxy.
+    #[serde(default)] pub real_ip_header: Option<String>,
+    /// Proxies appending to `real_ip_header`, entries left of theirs are
+    /// sent by the client and can't be trusted.
+    #[serde(default = "default_trusted_proxies")] pub trusted_proxies: usize,
+}
+
+fn default_trusted_proxies() -> usize {
+    1
+}
+
+fn default_account_create() -> RouteLimits {
+    RouteLimits {
+        per_ip: Some(BucketConfig { burst: 3, per_hour: 3 }),
+        per_account: None,
+    }
+}
+
+fn default_character_create() -> RouteLimits {
+    RouteLimits {
+        per_ip: Some(BucketConfig { burst: 10, per_hour: 20 }),
+        per_account: Some(BucketConfig { burst: 5, per_hour: 10 }),
+    }
+}
+
+fn default_check_name() -> RouteLimits {
+    RouteLimits {
+        per_ip: Some(BucketConfig { burst: 30, per_hour: 600 }),
+        per_account: None,
+    }
+}
+
+fn default_password_reset() -> RouteLimits {
+    RouteLimits {
+        per_ip: Some(BucketConfig { burst: 5, per_hour: 10 }),
+        per_account: None,
+    }
+}
+
+fn default_email_verify() -> RouteLimits {
+    RouteLimits {
+        per_ip: Some(BucketConfig { burst: 5, per_hour: 10 }),
+        per_account: Some(BucketConfig { burst: 3, per_hour: 3 }),
+    }
+}
+
+fn default_failed_login() -> Option<BucketConfig> {
+    Some(BucketConfig { burst: 10, per_hour: 30 })
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            account_create: default_account_create(),
+            character_create: default_character_create(),
+            check_name: default_check_name(),
+            password_reset: default_password_reset(),
+            email_verify: default_email_verify(),
+            failed_login: default_failed_login(),
+            real_ip_header: None,
+            trusted_proxies: default_trusted_proxies(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Route {
+    AccountCreate,
+    CharacterCreate,
+    CheckName,
+    PasswordReset,
+    EmailVerify,
+}
+
+impl RateLimits {
+    pub fn route(&self, route: Route) -> &RouteLimits {
+        match route {
+            Route::AccountCreate => &self.account_create,
+            Route::CharacterCreate => &self.character_create,
+            Route::CheckName => &self.check_name,
+            Route::PasswordReset => &self.password_reset,
+            Route::EmailVerify => &self.email_verify,
+        }
+    }
+
+    /// Address of the client, as added to the real IP header by the first
+    /// trusted proxy, or the connection's when there is no such entry.
+    pub fn client_ip(&self, headers: &HeaderMap, remote: Option<IpAddr>) -> Option<IpAddr> {
+        let forwarded = self.real_ip_header.as_ref().and_then(|name| {
+            let entries: Vec<_> = headers
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .collect();
+            entries
+                .len()
+                .checked_sub(self.trusted_proxies.max(1))
+                .and_then(|index| entries[index].trim().parse().ok())
+        });
+        forwarded.or(remote)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Subject {
+    Ip(IpAddr),
+    Account(u32),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant,
+}
+
+impl Bucket {
+    fn new(config: &BucketConfig, now: Instant) -> Self {
+        Self {
+            tokens: f64::from(config.burst),
+            updated: now,
+            full_at: now,
+        }
+    }
+
+    fn refill(&mut self, config: &BucketConfig, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * config.per_second()).min(f64::from(config.burst));
+        self.updated = now;
+    }
+
+    fn take(&mut self, config: &BucketConfig) {
+        self.tokens -= 1.0;
+        let missing = f64::from(config.burst) - self.tokens;
+        self.full_at = self.updated + Duration::from_secs_f64(missing / config.per_second());
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(Route, Subject), Bucket>>,
+    /// Failed logins by client address and upper case username.
+    failures: Mutex<HashMap<(Option<IpAddr>, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Takes a token for the client's address, before the request is
+    /// authenticated.
+    pub fn check_ip(
+        &self,
+        route: Route,
+        limits: &RouteLimits,
+        ip: Option<IpAddr>,
+    ) -> AppResult<()> {
+        match (limits.per_ip, ip) {
+            (Some(config), Some(ip)) => {
+                self.take(route, &[(Subject::Ip(ip), config)], Instant::now())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes a token for the authenticated caller's account. Requests signed
+    /// with an API key are not limited.
+    pub fn check_account(
+        &self,
+        route: Route,
+        limits: &RouteLimits,
+        caller: Option<&Caller>,
+    ) -> AppResult<()> {
+        match (limits.per_account, caller) {
+            (Some(config), Some(caller)) if caller.key.is_none() => {
+                let subjects = [(Subject::Account(caller.account), config)];
+                self.take(route, &subjects, Instant::now())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects logins to `username` from the address once its failures used up
+    /// the bucket, without taking a token.
+    pub fn check_login(
+        &self,
+        config: Option<&BucketConfig>,
+        ip: Option<IpAddr>,
+        username: &str,
+    ) -> AppResult<()> {
+        self.check_login_at(config, ip, username, Instant::now())
+    }
+
+    /// Takes a token for a failed login.
+    pub fn fail_login(&self, config: Option<&BucketConfig>, ip: Option<IpAddr>, username: &str) {
+        self.fail_login_at(config, ip, username, Instant::now())
+    }
+
+    fn check_login_at(
+        &self,
+        config: Option<&BucketConfig>,
+        ip: Option<IpAddr>,
+        username: &str,
+        now: Instant,
+    ) -> AppResult<()> {
+        let config = match config {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+        let mut failures = self.failures.lock().unwrap();
+        match failures.get_mut(&(ip, username.to_uppercase())) {
+            Some(bucket) => {
+                bucket.refill(config, now);
+                if bucket.tokens < 1.0 {
+                    let wait = (1.0 - bucket.tokens) / config.per_second();
+                    Err(AppError::TooManyRequests(wait.ceil() as u64))
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn fail_login_at(
+        &self,
+        config: Option<&BucketConfig>,
+        ip: Option<IpAddr>,
+        username: &str,
+        now: Instant,
+    ) {
+        let config = match config {
+            Some(config) => config,
+            None => return,
+        };
+        let mut failures = self.failures.lock().unwrap();
+        if failures.len() > MAX_BUCKETS {
+            failures.retain(|_, bucket| bucket.full_at > now);
+        }
+        let bucket = failures
+            .entry((ip, username.to_uppercase()))
+            .or_insert_with(|| Bucket::new(config, now));
+        bucket.refill(config, now);
+        if bucket.tokens >= 1.0 {
+            bucket.take(config);
+        }
+    }
+
+    fn take(
+        &self,
+        route: Route,
+        subjects: &[(Subject, BucketConfig)],
+        now: Instant,
+    ) -> AppResult<()> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|_, bucket| bucket.full_at > now);
+        }
+
+        // every bucket needs a token before any is taken
+        let mut wait: f64 = 0.0;
+        for (subject, config) in subjects {
+            let bucket = buckets
+                .entry((route, *subject))
+                .or_insert_with(|| Bucket::new(config, now));
+            bucket.refill(config, now);
+            if bucket.tokens < 1.0 {
+                wait = wait.max((1.0 - bucket.tokens) / config.per_second());
+            }
+        }
+        if wait > 0.0 {
+            return Err(AppError::TooManyRequests(wait.ceil() as u64));
+        }
+        for (subject, config) in subjects {
+            if let Some(bucket) = buckets.get_mut(&(route, *subject)) {
+                bucket.take(config);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn token_buckets() {
+        let limiter = RateLimiter::default();
+        let ip = Subject::Ip(IpAddr::from([127, 0, 0, 1]));
+        let account = Subject::Account(1);
+        let loose = BucketConfig { burst: 5, per_hour: 60 };
+        let tight = BucketConfig { burst: 2, per_hour: 60 };
+        let start = Instant::now();
+
+        let subjects = [(ip, loose), (account, tight)];
+        assert!(limiter.take(Route::CharacterCreate, &subjects, start).is_ok());
+        assert!(limiter.take(Route::CharacterCreate, &subjects, start).is_ok());
+        match limiter.take(Route::CharacterCreate, &subjects, start) {
+            Err(AppError::TooManyRequests(60)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        // the rejected request took no token from the address
+        let other = [(ip, loose), (Subject::Account(2), loose)];
+        for _ in 0..3 {
+            assert!(limiter.take(Route::CharacterCreate, &other, start).is_ok());
+        }
+        assert!(limiter.take(Route::CharacterCreate, &other, start).is_err());
+        // routes are counted apart
+        assert!(limiter.take(Route::CheckName, &subjects, start).is_ok());
+
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.take(Route::CharacterCreate, &subjects, later).is_ok());
+    }
+
+    #[test]
+    fn failed_logins() {
+        let limiter = RateLimiter::default();
+        let config = BucketConfig { burst: 2, per_hour: 60 };
+        let ip = Some(IpAddr::from([127, 0, 0, 1]));
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            assert!(limiter.check_login_at(Some(&config), ip, "player", start).is_ok());
+            limiter.fail_login_at(Some(&config), ip, "player", start);
+        }
+        match limiter.check_login_at(Some(&config), ip, "PLAYER", start) {
+            Err(AppError::TooManyRequests(60)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        // other usernames and addresses are counted apart
+        assert!(limiter.check_login_at(Some(&config), ip, "other", start).is_ok());
+        assert!(limiter.check_login_at(Some(&config), None, "player", start).is_ok());
+
+        let later = start + Duration::from_secs(60);
+        assert!(limiter.check_login_at(Some(&config), ip, "player", later).is_ok());
+    }
+
+    #[test]
+    fn client_address() {
+        let mut limits = RateLimits {
+            real_ip_header: Some("x-forwarded-for".to_owned()),
+            ..RateLimits::default()
+        };
+        let remote = Some(IpAddr::from([10, 0, 0, 1]));
+        let mut headers = HeaderMap::new();
+        assert_eq!(limits.client_ip(&headers, remote), remote);
+
+        headers.insert("x-forwarded-for", "1.1.1.1, 2.2.2.2".parse().unwrap());
+        headers.append("x-forwarded-for", "3.3.3.3".parse().unwrap());
+        assert_eq!(limits.client_ip(&headers, remote), Some(IpAddr::from([3, 3, 3, 3])));
+        limits.trusted_proxies = 2;
+        assert_eq!(limits.client_ip(&headers, remote), Some(IpAddr::from([2, 2, 2, 2])));
+        limits.trusted_proxies = 4;
+        assert_eq!(limits.client_ip(&headers, remote), remote);
+    }
+}
@@ -0,0 +1,127 @@
+use lettre::{
+    smtp::authentication::Credentials,
+    ClientSecurity,
+    ClientTlsParameters,
+    SmtpClient,
+    Transport,
+};
+use lettre_email::EmailBuilder;
+use serde::Deserialize;
+use crate::{
+    db::{self, token::Purpose},
+    error::{AppError, AppResult},
+    init::CtxRef,
+};
+
+fn default_port() -> u16 { 25 }
+fn default_verify_ttl() -> u64 { 2 * 24 * 3600 }
+fn default_reset_ttl() -> u64 { 3600 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// Plain connection, for local relays and test sinks.
+    None,
+    StartTls,
+    Tls,
+}
+
+impl Default for Security {
+    fn default() -> Self {
+        Security::None
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MailConfig {
+    pub host: String,
+    #[serde(default = "default_port")] pub port: u16,
+    #[serde(default)] pub security: Security,
+    #[serde(default)] pub username: Option<String>,
+    #[serde(default)] pub password: Option<String>,
+    pub from: String,
+    /// Website pages receiving the token as `token` query parameter.
+    pub verify_url: String,
+    pub reset_url: String,
+    /// Key signing verification and reset tokens.
+    pub token_secret: String,
+    /// Token lifetimes in seconds.
+    #[serde(default = "default_verify_ttl")] pub verify_ttl: u64,
+    #[serde(default = "default_reset_ttl")] pub reset_ttl: u64,
+}
+
+/// Mails a link confirming that `email` belongs to the account.
+pub async fn send_verification(ctx: &CtxRef, account: u32, email: &str) -> AppResult<()> {
+    let config = ctx.mail.as_ref().ok_or(AppError::NotFound)?;
+    let token = db::token::issue(
+        ctx.auth_db.clone(),
+        &config.token_secret,
+        account,
+        Purpose::Verify,
+        Some(email),
+        config.verify_ttl,
+    )
+    .await?;
+    let body = format!(
+        "Please confirm your email address by opening this link:\n\n{}\n",
+        link(&config.verify_url, &token)
+    );
+    send(config, email, "Confirm your email address", body).await
+}
+
+/// Mails a link for setting a new password.
+pub async fn send_reset(ctx: &CtxRef, account: u32, email: &str) -> AppResult<()> {
+    let config = ctx.mail.as_ref().ok_or(AppError::NotFound)?;
+    let token = db::token::issue(
+        ctx.auth_db.clone(),
+        &config.token_secret,
+        account,
+        Purpose::Reset,
+        None,
+        config.reset_ttl,
+    )
+    .await?;
+    let body = format!(
+        "A new password was requested for your account. Open this link to set it:\n\n{}\n\n\
+         If you did not ask for this, you can ignore this email.\n",
+        link(&config.reset_url, &token)
+    );
+    send(config, email, "Reset your password", body).await
+}
+
+fn link(base: &str, token: &str) -> String {
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", base, separator, token)
+}
+
+async fn send(config: &MailConfig, to: &str, subject: &str, body: String) -> AppResult<()> {
+    let email = EmailBuilder::new()
+        .to(to)
+        .from(config.from.as_str())
+        .subject(subject)
+        .text(body)
+        .build()
+        .map_err(|_| AppError::InvalidInput("email"))?;
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || -> anyhow::Result<()> {
+        let address = (config.host.as_str(), config.port);
+        let tls = || -> anyhow::Result<ClientTlsParameters> {
+            let connector = native_tls::TlsConnector::new()?;
+            Ok(ClientTlsParameters::new(config.host.clone(), connector))
+        };
+        let mut client = match config.security {
+            Security::None => SmtpClient::new(address, ClientSecurity::None)?,
+            Security::StartTls => SmtpClient::new(address, ClientSecurity::Required(tls()?))?,
+            Security::Tls => SmtpClient::new(address, ClientSecurity::Wrapper(tls()?))?,
+        };
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            client = client.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        client.transport().send(email.into())?;
+        Ok(())
+    })
+    .await
+    .map_err(anyhow::Error::from)??;
+    Ok(())
+}
@@ -1,9 +1,13 @@
 #![feature(async_closure)]
 
+mod auth;
 mod db;
+mod dbc;
 mod error;
 mod framework;
 mod init;
+mod limit;
+mod mail;
 mod util;
 mod web;
 
@@ -15,11 +19,20 @@ async fn main() -> anyhow::Result<()> {
     let mut args = std::env::args();
     args.next();
     let config_path = args.next().unwrap_or("config.yml".to_owned());
+    // `terra config.yml drift` reports characters out of date with the campaign
+    let command = args.next();
+    if let Some(command) = command.as_deref().filter(|&c| c != "drift") {
+        anyhow::bail!("unknown command {:?}, expected drift", command);
+    }
     let config: init::AppConfig =
         tokio::task::spawn_blocking(move || util::load_yaml(config_path)).await??;
 
     let listen = config.listen.clone();
     let ctx = tokio::task::spawn_blocking(move || init::create_context(config)).await??;
+    if command.is_some() {
+        return print_drift(&ctx).await;
+    }
+    tokio::spawn(init::rename_task(ctx.clone()));
     let app = web::create_server(ctx);
 
     warp::serve(app)
@@ -30,3 +43,29 @@ async fn main() -> anyhow::Result<()> {
     log::info!("Cleaning up before exit...");
     Ok(())
 }
+
+/// Lists characters that no longer fit their realm's campaign.
+async fn print_drift(ctx: &init::CtxRef) -> anyhow::Result<()> {
+    for realm in &ctx.realms {
+        let report = db::drift::scan(realm.chars_db.clone(), &realm.campaign).await?;
+        println!(
+            "Realm {}: {} of {} characters drifted from campaign {}",
+            realm.id,
+            report.characters.len(),
+            report.checked,
+            report.campaign_version
+        );
+        for character in &report.characters {
+            let version = character.campaign_version.as_deref().unwrap_or("unknown");
+            let outdated = if character.outdated { ", outdated" } else { "" };
+            println!(
+                "  {} {} (account {}, campaign {}{})",
+                character.guid, character.name, character.account, version, outdated
+            );
+            for problem in &character.problems {
+                println!("    {}/{}: {:?}", problem.field, problem.id, problem.issue);
+            }
+        }
+    }
+    Ok(())
+}
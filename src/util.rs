@@ -1,4 +1,4 @@
-use std::{fs::File, io::BufReader, path::Path};
+use std::{collections::HashMap, fs::File, io::{BufReader, Read}, path::Path};
 use log::info;
 use once_cell::sync::Lazy;
 use regex::{Regex, RegexBuilder};
@@ -30,6 +30,16 @@ pub fn hexstring<T: AsRef<[u8]>>(input: T) -> String {
     output
 }
 
+/// Hex string of `size` random bytes from the system generator.
+pub fn random_hex(size: usize) -> anyhow::Result<String> {
+    use ring::rand::{SecureRandom, SystemRandom};
+    let mut bytes = vec![0u8; size];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow::anyhow!("no randomness available"))?;
+    Ok(hexstring(bytes))
+}
+
 pub fn prepare_name(input: &str) -> String {
     capitalize(WHITESPACE_REGEX.replace(&input.trim().to_lowercase(), " "))
 }
@@ -67,6 +77,12 @@ where
 pub fn load_markdown<P: AsRef<Path>>(path: P) -> anyhow::Result<String> {
     info!("Loading file {:?}", path.as_ref());
     let source = std::fs::read_to_string(path.as_ref())?;
+    Ok(render_markdown(&source))
+}
+
+/// Renders Markdown to HTML. Raw HTML and unsafe links are left out, so the
+/// output is fine to embed even when the source comes from players.
+pub fn render_markdown(source: &str) -> String {
     let options = comrak::ComrakOptions {
         smart: true,
         ext_strikethrough: true,
@@ -77,5 +93,30 @@ pub fn load_markdown<P: AsRef<Path>>(path: P) -> anyhow::Result<String> {
         ext_footnotes: true,
         ..Default::default()
     };
-    Ok(comrak::markdown_to_html(&source, &options))
+    comrak::markdown_to_html(source, &options)
+}
+
+/// Reads a csv file with a header row into one map per record.
+pub fn load_csv<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<HashMap<String, String>>> {
+    info!("Loading file {:?}", path.as_ref());
+    read_csv(File::open(path.as_ref())?)
+}
+
+fn read_csv<R: Read>(reader: R) -> anyhow::Result<Vec<HashMap<String, String>>> {
+    csv::Reader::from_reader(reader)
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .map_err(From::from)
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    fn read_csv() {
+        let records = super::read_csv(&b"id,name\r\n1,\"Sword, \"\"Big\"\"\"\n2,Shield\n"[..]).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["id"], "1");
+        assert_eq!(records[0]["name"], "Sword, \"Big\"");
+        assert_eq!(records[1]["name"], "Shield");
+    }
 }
@@ -1,8 +1,20 @@
-use http::StatusCode;
-use serde::Deserialize;
-use serde_json::json;
-use warp::{filters::BoxedFilter, reply::Json, Filter, Rejection, Reply};
-use crate::{db, error, init::CtxRef};
+use std::net::SocketAddr;
+use bytes::Bytes;
+use http::{HeaderMap, Method, StatusCode};
+use log::error;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value as JsonValue};
+use warp::{filters::BoxedFilter, path::FullPath, reply::Json, Filter, Rejection, Reply};
+use crate::{
+    auth::{self, Caller, Permission},
+    db::{self, token::Purpose},
+    error::{self, AppError, AppResult},
+    framework::{campaign::Campaign, profile::Profile, visibility::Audience},
+    init::CtxRef,
+    limit::Route,
+    mail,
+    util,
+};
 
 type FilterResult<T> = Result<T, Rejection>;
 type JsonResult = FilterResult<Json>;
@@ -16,128 +28,736 @@ where
 
 pub fn create_server(ctx: CtxRef) -> BoxedFilter<(impl Reply,)> {
     let campaign_read = warp::get()
+        .and(realm(ctx.clone()))
         .and(warp::path!("campaign"))
         .and(with(ctx.clone()))
-        .map(|ctx: CtxRef| {
-            warp::reply::json(&json!({
-                "blocks": &ctx.campaign.blocks,
-                "role": &ctx.campaign.roles,
-                "location": &ctx.campaign.system_view.location,
-                "race": &ctx.campaign.system_view.race,
-                "class": &ctx.campaign.system_view.class,
-                "armor": &ctx.campaign.system_view.armor,
-                "weapon": &ctx.campaign.system_view.weapon,
-                "trait": &ctx.campaign.system_view.traits,
-            }))
-        });
+        .and_then(campaign_read_handler);
+
+    let campaign_drift = warp::get()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("campaign" / "drift"))
+        .and(caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(campaign_drift_handler);
+
+    let realm_list = warp::get()
+        .and(warp::path!("realms"))
+        .and(with(ctx.clone()))
+        .and_then(realm_list_handler);
 
     let account_read = warp::get()
         .and(warp::path!("accounts" / u32))
+        .and(caller(ctx.clone()))
+        .and(warp::query())
         .and(with(ctx.clone()))
         .and_then(account_read_handler);
 
     let account_create = warp::post()
         .and(warp::path!("accounts"))
-        .and(warp::body::json())
+        .and(limit(
+            ctx.clone(),
+            Route::AccountCreate,
+            optional_caller_json(ctx.clone()),
+        ))
         .and(with(ctx.clone()))
         .and_then(account_create_handler);
 
     let account_replace = warp::put()
         .and(warp::path!("accounts" / u32))
-        .and(warp::body::json())
+        .and(require_json(ctx.clone(), Permission::ManageAccounts))
         .and(with(ctx.clone()))
         .and_then(account_replace_handler);
 
+    let account_ban = warp::post()
+        .and(warp::path!("accounts" / u32 / "ban"))
+        .and(require_json(ctx.clone(), Permission::ManageAccounts))
+        .and(with(ctx.clone()))
+        .and_then(account_ban_handler);
+
+    let account_unban = warp::delete()
+        .and(warp::path!("accounts" / u32 / "ban"))
+        .and(require(ctx.clone(), Permission::ManageAccounts))
+        .and(with(ctx.clone()))
+        .and_then(account_unban_handler);
+
+    let account_email = warp::get()
+        .and(warp::path!("accounts" / u32 / "email"))
+        .and(caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(account_email_handler);
+
+    let account_email_send = warp::post()
+        .and(warp::path!("accounts" / u32 / "email" / "verify"))
+        .and(limit(
+            ctx.clone(),
+            Route::EmailVerify,
+            optional_caller_body(ctx.clone()),
+        ))
+        .and(with(ctx.clone()))
+        .and_then(account_email_send_handler);
+
+    let account_email_verify = warp::post()
+        .and(warp::path!("accounts" / "verify-email"))
+        .and(warp::body::json())
+        .and(with(ctx.clone()))
+        .and_then(account_email_verify_handler);
+
+    let account_reset_request = warp::post()
+        .and(warp::path!("accounts" / "reset-password" / "request"))
+        .and(limit(
+            ctx.clone(),
+            Route::PasswordReset,
+            optional_caller_json(ctx.clone()),
+        ))
+        .and(with(ctx.clone()))
+        .and_then(account_reset_request_handler);
+
+    let account_reset = warp::post()
+        .and(warp::path!("accounts" / "reset-password"))
+        .and(warp::body::json())
+        .and(with(ctx.clone()))
+        .and_then(account_reset_handler);
+
     let account_update = warp::patch()
         .and(warp::path!("accounts" / u32))
-        .and(warp::body::json())
+        .and(caller_json(ctx.clone()))
         .and(with(ctx.clone()))
         .and_then(account_update_handler);
 
+    let invite_create = warp::post()
+        .and(warp::path!("invites"))
+        .and(require_json(ctx.clone(), Permission::ManageAccounts))
+        .and(with(ctx.clone()))
+        .and_then(invite_create_handler);
+
+    let invite_list = warp::get()
+        .and(warp::path!("invites"))
+        .and(require(ctx.clone(), Permission::ManageAccounts))
+        .and(with(ctx.clone()))
+        .and_then(invite_list_handler);
+
+    let invite_revoke = warp::delete()
+        .and(warp::path!("invites" / String))
+        .and(require(ctx.clone(), Permission::ManageAccounts))
+        .and(with(ctx.clone()))
+        .and_then(invite_revoke_handler);
+
     let character_create = warp::post()
+        .and(realm(ctx.clone()))
         .and(warp::path!("characters"))
-        .and(warp::body::json())
+        .and(limit(
+            ctx.clone(),
+            Route::CharacterCreate,
+            optional_caller_json(ctx.clone()),
+        ))
         .and(with(ctx.clone()))
         .and_then(character_create_handler);
 
     let character_list_mine = warp::get()
+        .and(realm(ctx.clone()))
         .and(warp::path!("characters" / "mine" / u32))
+        .and(caller(ctx.clone()))
+        .and(warp::query())
         .and(with(ctx.clone()))
         .and_then(character_list_mine_handler);
 
     let character_list_other = warp::get()
+        .and(realm(ctx.clone()))
         .and(warp::path!("characters" / "other" / u32))
+        .and(caller(ctx.clone()))
+        .and(warp::query())
         .and(with(ctx.clone()))
         .and_then(character_list_other_handler);
 
     let character_read = warp::get()
+        .and(realm(ctx.clone()))
         .and(warp::path!("characters" / "guid" / u32))
+        .and(optional_caller(ctx.clone()))
         .and(with(ctx.clone()))
         .and_then(character_read_handler);
 
+    let character_profile = warp::get()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "profile"))
+        .and(optional_caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_profile_handler);
+
+    let character_rename = warp::post()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "rename"))
+        .and(caller_json(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_rename_handler);
+
+    let character_force_rename = warp::post()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "force-rename"))
+        .and(caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_force_rename_handler);
+
+    let character_at_login = warp::post()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "at-login"))
+        .and(caller_json(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_at_login_handler);
+
+    let character_audit = warp::get()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "audit"))
+        .and(caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_audit_handler);
+
     let character_check_name = warp::post()
+        .and(realm(ctx.clone()))
         .and(warp::path!("characters" / "check-name"))
-        .and(warp::body::json())
+        .and(limit(
+            ctx.clone(),
+            Route::CheckName,
+            optional_caller_json(ctx.clone()),
+        ))
         .and(with(ctx.clone()))
         .and_then(character_check_name_handler);
 
+    let character_biography_edit = warp::patch()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "biography"))
+        .and(caller_json(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_biography_edit_handler);
+
+    let character_revision_list = warp::get()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "revisions"))
+        .and(caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_revision_list_handler);
+
+    let character_revision_approve = warp::post()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "revisions" / u32 / "approve"))
+        .and(caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_revision_approve_handler);
+
+    let character_revision_revert = warp::post()
+        .and(realm(ctx.clone()))
+        .and(warp::path!("characters" / "guid" / u32 / "revisions" / u32 / "revert"))
+        .and(caller(ctx.clone()))
+        .and(with(ctx.clone()))
+        .and_then(character_revision_revert_handler);
+
     campaign_read
+        .or(campaign_drift)
+        .or(realm_list)
         .or(account_read)
         .or(account_create)
         .or(account_replace)
         .or(account_update)
+        .or(account_ban)
+        .or(account_unban)
+        .or(account_email)
+        .or(account_email_send)
+        .or(account_email_verify)
+        .or(account_reset_request)
+        .or(account_reset)
+        .or(invite_create)
+        .or(invite_list)
+        .or(invite_revoke)
         .or(character_create)
         .or(character_list_mine)
         .or(character_list_other)
         .or(character_read)
+        .or(character_profile)
+        .or(character_rename)
+        .or(character_force_rename)
+        .or(character_at_login)
+        .or(character_audit)
         .or(character_check_name)
+        .or(character_biography_edit)
+        .or(character_revision_list)
+        .or(character_revision_approve)
+        .or(character_revision_revert)
         .recover(error::handle_rejection)
         .with(warp::log::log("terra"))
         .boxed()
 }
 
-async fn account_read_handler(account: u32, ctx: CtxRef) -> JsonResult {
-    let data = db::account::read(ctx.auth_db.clone(), account).await?;
+async fn campaign_read_handler(realm: u32, ctx: CtxRef) -> JsonResult {
+    let campaign = &ctx.realm(realm)?.campaign;
+    Ok(warp::reply::json(&json!({
+        "version": &campaign.version,
+        "blocks": &campaign.blocks,
+        "role": &campaign.roles,
+        "location": &campaign.system_view.location,
+        "race": &campaign.system_view.race,
+        "class": &campaign.system_view.class,
+        "armor": &campaign.system_view.armor,
+        "weapon": &campaign.system_view.weapon,
+        "trait": &campaign.system_view.traits,
+    })))
+}
+
+async fn campaign_drift_handler(realm: u32, caller: Caller, ctx: CtxRef) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require(Permission::EditCampaign)?;
+    let data = db::drift::scan(realm.chars_db.clone(), &realm.campaign).await?;
     Ok(warp::reply::json(&data))
 }
 
+async fn realm_list_handler(ctx: CtxRef) -> JsonResult {
+    let mut data = db::realm::list(ctx.auth_db.clone()).await?;
+    for realm in &mut data {
+        if let Ok(known) = ctx.realm(realm.id) {
+            // one unreachable characters database leaves the others listed
+            match db::realm::status(known.chars_db.clone()).await {
+                Ok(status) => realm.status = Some(status),
+                Err(err) => error!("Unable to read the status of realm {}: {}", realm.id, err),
+            }
+        }
+    }
+    Ok(warp::reply::json(&data))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AccountView {
+    Basic,
+    /// Adds login data, access and character counts per realm.
+    Full,
+}
+
+impl Default for AccountView {
+    fn default() -> Self {
+        AccountView::Basic
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountQuery {
+    #[serde(default)]
+    view: AccountView,
+}
+
+async fn account_read_handler(
+    account: u32,
+    caller: Caller,
+    query: AccountQuery,
+    ctx: CtxRef,
+) -> JsonResult {
+    caller.require_owner(account, Permission::ViewAccounts)?;
+    let data = db::account::read(ctx.auth_db.clone(), account).await?;
+    let bans = db::ban::history(ctx.auth_db.clone(), account).await?;
+    let mut reply = json!({
+        "id": data.id,
+        "username": data.username,
+        "gmlevel": data.gmlevel,
+        "ban": bans.iter().find(|ban| ban.active),
+        "bans": bans,
+    });
+    if query.view == AccountView::Full {
+        let mut details = db::account::read_details(ctx.auth_db.clone(), account).await?;
+        if caller.account != account {
+            details.last_ip = None;
+        }
+        let email = db::account::read_email(ctx.auth_db.clone(), account).await?;
+        let mut characters = Vec::new();
+        for realm in &ctx.realms {
+            let count = db::character::count_owned(realm.chars_db.clone(), account).await?;
+            characters.push(json!({ "realm": realm.id, "count": count }));
+        }
+        reply["details"] = json!(details);
+        reply["email"] = json!(email);
+        reply["characters"] = json!(characters);
+    }
+    Ok(warp::reply::json(&reply))
+}
+
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 struct AccountCreate {
     username: String,
     password: String,
+    /// Left out on replace, the stored address is kept.
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    invite: Option<String>,
 }
 
-async fn account_create_handler(input: AccountCreate, ctx: CtxRef) -> JsonResult {
-    let id = db::account::create(ctx.auth_db.clone(), &input.username, &input.password).await?;
+impl AccountCreate {
+    fn check(&self, ctx: &CtxRef) -> AppResult<()> {
+        db::account::check_username(&self.username)?;
+        db::account::check_password(&self.username, &self.password, &ctx.password_rules)?;
+        db::account::check_email(self.email())
+    }
+
+    fn email(&self) -> &str {
+        self.email.as_deref().unwrap_or("")
+    }
+}
+
+async fn account_create_handler(
+    caller: Option<Caller>,
+    input: AccountCreate,
+    ctx: CtxRef,
+) -> JsonResult {
+    input.check(&ctx)?;
+    // staff may create accounts without a code
+    let needs_invite = ctx.require_invite
+        && !caller.map_or(false, |caller| caller.has(Permission::ManageAccounts));
+    let id = match &input.invite {
+        Some(code) => {
+            db::invite::create_account(
+                ctx.auth_db.clone(),
+                code.trim(),
+                &input.username,
+                &input.password,
+                input.email(),
+            )
+            .await?
+        }
+        None if needs_invite => return Err(AppError::InvalidInput("invite").into()),
+        None => {
+            db::account::create(
+                ctx.auth_db.clone(),
+                &input.username,
+                &input.password,
+                input.email(),
+            )
+            .await?
+        }
+    };
+    mail_verification(&ctx, id, input.email());
     Ok(warp::reply::json(&json!({ "id": id })))
 }
 
 async fn account_replace_handler(
     account: u32,
+    caller: Caller,
     input: AccountCreate,
     ctx: CtxRef,
 ) -> FilterResult<impl Reply> {
-    db::account::replace(
+    caller.require_above(&ctx, account).await?;
+    input.check(&ctx)?;
+    let changed = db::account::replace(
         ctx.auth_db.clone(),
         account,
         &input.username,
         &input.password,
+        input.email.as_deref(),
     )
     .await?;
+    if changed {
+        mail_verification(&ctx, account, input.email());
+    }
     Ok(warp::reply::with_status("", StatusCode::NO_CONTENT))
 }
 
 async fn account_update_handler(
     account: u32,
-    input: AccountCreate,
+    caller: Caller,
+    input: db::account::Patch,
+    ctx: CtxRef,
+) -> JsonResult {
+    caller.require_owner(account, Permission::ManageAccounts)?;
+    caller.require_above(&ctx, account).await?;
+    if let Some(username) = &input.username {
+        db::account::check_username(username)?;
+    }
+    if let Some(password) = &input.password {
+        let username = match &input.username {
+            Some(username) => username.clone(),
+            None => db::account::read(ctx.auth_db.clone(), account).await?.username,
+        };
+        db::account::check_password(&username, password, &ctx.password_rules)?;
+    }
+    if let Some(email) = &input.email {
+        db::account::check_email(email)?;
+    }
+    if let Some(country) = &input.lock_country {
+        db::account::check_lock_country(country)?;
+    }
+    let changed = db::account::update(ctx.auth_db.clone(), account, &input).await?;
+    if let (true, Some(email)) = (changed.contains(&"email"), &input.email) {
+        mail_verification(&ctx, account, email);
+    }
+    Ok(warp::reply::json(&json!({ "changed": changed })))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BanInput {
+    /// Seconds, permanent when left out.
+    #[serde(default)]
+    duration: Option<u32>,
+    reason: String,
+}
+
+async fn account_ban_handler(
+    account: u32,
+    caller: Caller,
+    input: BanInput,
+    ctx: CtxRef,
+) -> JsonResult {
+    caller.require_above(&ctx, account).await?;
+    let reason = input.reason.trim();
+    if reason.is_empty() {
+        return Err(AppError::InvalidInput("reason").into());
+    }
+    if input.duration == Some(0) {
+        return Err(AppError::InvalidInput("duration").into());
+    }
+    // the core shows who banned by name
+    let banned_by = match &caller.key {
+        Some(key) if caller.account == 0 => key.clone(),
+        _ => db::account::read(ctx.auth_db.clone(), caller.account).await?.username,
+    };
+    let ban = db::ban::ban(ctx.auth_db.clone(), account, &banned_by, input.duration, reason).await?;
+    db::audit::record(
+        &ctx.main_realm().chars_db,
+        caller.account,
+        "account_ban",
+        None,
+        json!({ "account": account, "duration": input.duration, "reason": reason }),
+    )
+    .await?;
+    Ok(warp::reply::json(&ban))
+}
+
+async fn account_unban_handler(
+    account: u32,
+    caller: Caller,
+    ctx: CtxRef,
+) -> FilterResult<impl Reply> {
+    caller.require_above(&ctx, account).await?;
+    db::ban::unban(ctx.auth_db.clone(), account).await?;
+    db::audit::record(
+        &ctx.main_realm().chars_db,
+        caller.account,
+        "account_unban",
+        None,
+        json!({ "account": account }),
+    )
+    .await?;
+    Ok(warp::reply::with_status("", StatusCode::NO_CONTENT))
+}
+
+/// Mails a verification for a new address in the background, if mail is set up.
+fn mail_verification(ctx: &CtxRef, account: u32, email: &str) {
+    if ctx.mail.is_none() || email.is_empty() {
+        return;
+    }
+    let ctx = ctx.clone();
+    let email = email.to_owned();
+    tokio::spawn(async move {
+        if let Err(err) = mail::send_verification(&ctx, account, &email).await {
+            error!("Unable to mail verification to account {}: {}", account, err);
+        }
+    });
+}
+
+async fn account_email_handler(account: u32, caller: Caller, ctx: CtxRef) -> JsonResult {
+    caller.require_owner(account, Permission::ViewAccounts)?;
+    let data = db::account::read_email(ctx.auth_db.clone(), account).await?;
+    Ok(warp::reply::json(&data))
+}
+
+async fn account_email_send_handler(
+    account: u32,
+    caller: Option<Caller>,
+    _body: Bytes,
     ctx: CtxRef,
 ) -> FilterResult<impl Reply> {
-    db::account::update(
+    let caller = caller.ok_or(AppError::Unauthorized)?;
+    caller.require_owner(account, Permission::ManageAccounts)?;
+    caller.require_above(&ctx, account).await?;
+    let data = db::account::read_email(ctx.auth_db.clone(), account).await?;
+    if data.email.is_empty() || data.verified {
+        return Err(AppError::InvalidInput("email").into());
+    }
+    mail::send_verification(&ctx, account, &data.email).await?;
+    Ok(warp::reply::with_status("", StatusCode::ACCEPTED))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenInput {
+    token: String,
+}
+
+async fn account_email_verify_handler(input: TokenInput, ctx: CtxRef) -> JsonResult {
+    let config = ctx.mail.as_ref().ok_or(AppError::NotFound)?;
+    let token = db::token::consume(
         ctx.auth_db.clone(),
-        account,
-        &input.username,
-        &input.password,
+        &config.token_secret,
+        &input.token,
+        Purpose::Verify,
+    )
+    .await?;
+    let email = token.email.unwrap_or_default();
+    db::account::verify_email(ctx.auth_db.clone(), token.account, &email).await?;
+    Ok(warp::reply::json(&json!({ "account": token.account, "email": email })))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResetRequest {
+    /// Username or verified email address.
+    login: String,
+}
+
+async fn account_reset_request_handler(
+    _caller: Option<Caller>,
+    input: ResetRequest,
+    ctx: CtxRef,
+) -> FilterResult<impl Reply> {
+    if ctx.mail.is_none() {
+        return Err(AppError::NotFound.into());
+    }
+    // the answer and its timing are the same for unknown accounts and failures,
+    // so they can't be used to probe for accounts
+    tokio::spawn(async move {
+        let sent = match db::account::find_verified(ctx.auth_db.clone(), &input.login).await {
+            Ok(Some((account, email))) => mail::send_reset(&ctx, account, &email).await,
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = sent {
+            error!("Unable to mail password reset for {:?}: {}", input.login, err);
+        }
+    });
+    Ok(warp::reply::with_status("", StatusCode::ACCEPTED))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ResetInput {
+    token: String,
+    password: String,
+}
+
+async fn account_reset_handler(input: ResetInput, ctx: CtxRef) -> FilterResult<impl Reply> {
+    let config = ctx.mail.as_ref().ok_or(AppError::NotFound)?;
+    let secret = &config.token_secret;
+    // a password failing the rules leaves the token usable
+    let token = db::token::peek(ctx.auth_db.clone(), secret, &input.token, Purpose::Reset).await?;
+    let current = db::account::read(ctx.auth_db.clone(), token.account).await?;
+    db::account::check_password(&current.username, &input.password, &ctx.password_rules)?;
+
+    let token = db::token::consume(ctx.auth_db.clone(), secret, &input.token, Purpose::Reset).await?;
+    let patch = db::account::Patch {
+        password: Some(input.password),
+        ..Default::default()
+    };
+    db::account::update(ctx.auth_db.clone(), token.account, &patch).await?;
+    db::token::revoke(ctx.auth_db.clone(), token.account, Purpose::Reset).await?;
+    Ok(warp::reply::with_status("", StatusCode::NO_CONTENT))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InviteCreate {
+    /// Generated when left out.
+    #[serde(default)]
+    code: Option<String>,
+    /// Seconds until the code stops working, never when left out.
+    #[serde(default)]
+    expires_in: Option<u32>,
+    #[serde(default = "default_invite_uses")]
+    max_uses: u32,
+    /// Realm of the role or block, the main one when left out.
+    #[serde(default)]
+    realm: Option<u32>,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    block: Option<String>,
+}
+
+fn default_invite_uses() -> u32 { 1 }
+
+const INVITE_CODE_SIZE: usize = 8;
+const INVITE_CODE_MAX: usize = 32;
+
+async fn invite_create_handler(caller: Caller, input: InviteCreate, ctx: CtxRef) -> JsonResult {
+    let code = match input.code {
+        Some(code) => code.trim().to_owned(),
+        None => util::random_hex(INVITE_CODE_SIZE).map_err(AppError::from)?,
+    };
+    if code.is_empty()
+        || code.len() > INVITE_CODE_MAX
+        || !code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(AppError::InvalidInput("code").into());
+    }
+    if input.max_uses == 0 {
+        return Err(AppError::InvalidInput("max_uses").into());
+    }
+    let realm = match input.realm {
+        Some(id) => ctx.realm(id).map_err(|_| AppError::InvalidInput("realm"))?,
+        None => ctx.main_realm(),
+    };
+    if let Some(role) = &input.role {
+        if !realm.campaign.roles.contains_key(role) {
+            return Err(AppError::InvalidInput("role").into());
+        }
+    }
+    if let Some(block) = &input.block {
+        if !realm.campaign.blocks.iter().any(|b| &b.id == block) {
+            return Err(AppError::InvalidInput("block").into());
+        }
+    }
+    let reserved = input.role.is_some() || input.block.is_some();
+    let invite = db::invite::create(
+        ctx.auth_db.clone(),
+        &code,
+        caller.account,
+        input.expires_in,
+        input.max_uses,
+        Some(realm.id).filter(|_| reserved),
+        input.role.as_deref(),
+        input.block.as_deref(),
+    )
+    .await?;
+    db::audit::record(
+        &ctx.main_realm().chars_db,
+        caller.account,
+        "invite_create",
+        None,
+        json!({
+            "code": invite.code,
+            "realm": invite.realm,
+            "role": invite.role,
+            "block": invite.block,
+        }),
+    )
+    .await?;
+    Ok(warp::reply::json(&invite))
+}
+
+async fn invite_list_handler(_caller: Caller, ctx: CtxRef) -> JsonResult {
+    let data = db::invite::list(ctx.auth_db.clone()).await?;
+    Ok(warp::reply::json(&data))
+}
+
+async fn invite_revoke_handler(
+    code: String,
+    caller: Caller,
+    ctx: CtxRef,
+) -> FilterResult<impl Reply> {
+    db::invite::revoke(ctx.auth_db.clone(), &code).await?;
+    db::audit::record(
+        &ctx.main_realm().chars_db,
+        caller.account,
+        "invite_revoke",
+        None,
+        json!({ "code": code }),
     )
     .await?;
     Ok(warp::reply::with_status("", StatusCode::NO_CONTENT))
@@ -151,33 +771,531 @@ struct CharacterCreate {
     form: db::character::Form,
 }
 
-async fn character_create_handler(input: CharacterCreate, ctx: CtxRef) -> JsonResult {
-    let cdata = input.form.into_cdata(&ctx.campaign)?;
-    let guid = db::character::create(ctx.chars_db.clone(), input.account, cdata).await?;
-    Ok(warp::reply::json(&json!({ "guid": guid })))
+async fn character_create_handler(
+    realm: u32,
+    caller: Option<Caller>,
+    input: CharacterCreate,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.ok_or(AppError::Unauthorized)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require_owner(input.account, Permission::ManageCharacters)?;
+    db::ban::check(ctx.auth_db.clone(), input.account).await?;
+    let role = &input.form.role;
+    let block = realm
+        .campaign
+        .blocks
+        .iter()
+        .find(|block| block.roles.contains(role))
+        .map(|block| block.id.as_str());
+    let available =
+        db::invite::role_available(ctx.auth_db.clone(), realm.id, input.account, role, block)
+            .await?;
+    if !available {
+        return Err(AppError::InvalidInput("role_reserved").into());
+    }
+    let cdata = input
+        .form
+        .into_cdata(&realm.campaign, ctx.client_data.as_ref())?;
+    let contents = cdata.contents(&ctx.catalog);
+    // stock cores hand out guids from memory, so their worldserver has to stay down
+    let hold = match realm.chars_backend {
+        db::Backend::Trinity => {
+            Some(db::realm::hold_offline(ctx.auth_db.clone(), realm.id).await?)
+        }
+        db::Backend::Skyland => None,
+    };
+    let guid = db::character::create(
+        realm.chars_db.clone(),
+        realm.chars_backend,
+        &ctx.catalog,
+        input.account,
+        cdata,
+    )
+    .await?;
+    drop(hold);
+    Ok(warp::reply::json(&json!({ "guid": guid, "contents": contents })))
+}
+
+async fn character_list_mine_handler(
+    realm: u32,
+    account: u32,
+    caller: Caller,
+    filter: db::character::ListFilter,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require_owner(account, Permission::ManageCharacters)?;
+    let owner = db::character::Owner::Account(account);
+    let mut data =
+        db::character::list(realm.chars_db.clone(), owner, &filter, &realm.campaign).await?;
+    for character in &mut data.items {
+        redact(&realm.campaign, Audience::Owner, character);
+    }
+    Ok(warp::reply::json(&data))
+}
+
+async fn character_list_other_handler(
+    realm: u32,
+    account: u32,
+    caller: Caller,
+    filter: db::character::ListFilter,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require_owner(account, Permission::ManageCharacters)?;
+    let audience = if caller.has(Permission::ViewHidden) {
+        Audience::Gm
+    } else {
+        Audience::Other
+    };
+    // filtering on a hidden field or id would reveal it
+    let rule = realm.campaign.visibility.rule(audience);
+    let hidden = |field: &str, id: &str| rule.hides(&realm.campaign, field, id);
+    let roles = filter.roles(&realm.campaign)?.unwrap_or_default();
+    if filter.race.as_ref().map_or(false, |id| hidden("race", id))
+        || filter.class.as_ref().map_or(false, |id| hidden("class", id))
+        || filter.role.iter().chain(&roles).any(|id| hidden("role", id))
+        || (filter.block.is_some() && rule.hide_fields.contains("role"))
+    {
+        return Err(AppError::InvalidInput("filter").into());
+    }
+
+    let owner = db::character::Owner::NotAccount(account);
+    let mut data =
+        db::character::list(realm.chars_db.clone(), owner, &filter, &realm.campaign).await?;
+    for character in &mut data.items {
+        redact(&realm.campaign, audience, character);
+    }
+    Ok(warp::reply::json(&data))
+}
+
+async fn character_read_handler(
+    realm: u32,
+    guid: u32,
+    caller: Option<Caller>,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = match caller {
+        Some(caller) => Some(caller.on_realm(&ctx, realm.id).await?),
+        None => None,
+    };
+    let mut data = db::character::read(realm.chars_db.clone(), guid).await?;
+    redact(&realm.campaign, audience(caller.as_ref(), data.account), &mut data);
+    Ok(warp::reply::json(&data))
+}
+
+async fn character_profile_handler(
+    realm: u32,
+    guid: u32,
+    caller: Option<Caller>,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = match caller {
+        Some(caller) => Some(caller.on_realm(&ctx, realm.id).await?),
+        None => None,
+    };
+    let mut data = db::character::read(realm.chars_db.clone(), guid).await?;
+    redact(&realm.campaign, audience(caller.as_ref(), data.account), &mut data);
+    let profile = Profile::new(&realm.campaign, data.female, &data.metadata);
+    Ok(warp::reply::json(&json!({
+        "guid": data.guid,
+        "name": data.name,
+        "name_extra": data.name_extra,
+        "female": data.female,
+        "level": data.level,
+        "online": data.online,
+        "profile": profile,
+    })))
+}
+
+fn audience(caller: Option<&Caller>, owner: u32) -> Audience {
+    match caller {
+        Some(caller) if caller.account == owner => Audience::Owner,
+        Some(caller) if caller.has(Permission::ViewHidden) => Audience::Gm,
+        _ => Audience::Other,
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BiographyEdit {
+    #[serde(default)]
+    info: Option<String>,
+    #[serde(default)]
+    name_extra: Option<String>,
+}
+
+async fn character_biography_edit_handler(
+    realm: u32,
+    guid: u32,
+    caller: Caller,
+    input: BiographyEdit,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    let current = db::biography::current(realm.chars_db.clone(), guid).await?;
+    caller.require_owner(current.account, Permission::ManageCharacters)?;
+    // absent fields stay as they are, empty ones are cleared
+    let info = match input.info {
+        Some(s) => Some(s.trim().to_owned()).filter(|s| !s.is_empty()),
+        None => current.info,
+    };
+    let name_extra = match input.name_extra {
+        Some(s) => db::character::check_name_extra(Some(&s))?,
+        None => current.name_extra,
+    };
+    let pending = current.locked && realm.campaign.editing.reapprove_locked;
+    let revision = db::biography::edit(
+        realm.chars_db.clone(),
+        realm.chars_backend,
+        guid,
+        caller.account,
+        info,
+        name_extra,
+        pending,
+    )
+    .await?;
+    Ok(warp::reply::json(&revision))
+}
+
+async fn character_revision_list_handler(
+    realm: u32,
+    guid: u32,
+    caller: Caller,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require(Permission::ApproveCharacters)?;
+    let data = db::biography::list(realm.chars_db.clone(), guid).await?;
+    Ok(warp::reply::json(&data))
+}
+
+async fn character_revision_approve_handler(
+    realm: u32,
+    guid: u32,
+    revision: u32,
+    caller: Caller,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require(Permission::ApproveCharacters)?;
+    let data = db::biography::approve(
+        realm.chars_db.clone(),
+        realm.chars_backend,
+        guid,
+        revision,
+        caller.account,
+    )
+    .await?;
+    Ok(warp::reply::json(&data))
 }
 
-async fn character_list_mine_handler(account: u32, ctx: CtxRef) -> JsonResult {
-    let data = db::character::list_mine(ctx.chars_db.clone(), account).await?;
+async fn character_revision_revert_handler(
+    realm: u32,
+    guid: u32,
+    revision: u32,
+    caller: Caller,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require(Permission::ApproveCharacters)?;
+    let data = db::biography::revert(
+        realm.chars_db.clone(),
+        realm.chars_backend,
+        guid,
+        revision,
+        caller.account,
+    )
+    .await?;
     Ok(warp::reply::json(&data))
 }
 
-async fn character_list_other_handler(account: u32, ctx: CtxRef) -> JsonResult {
-    let data = db::character::list_other(ctx.chars_db.clone(), account).await?;
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rename {
+    name: String,
+}
+
+async fn character_rename_handler(
+    realm: u32,
+    guid: u32,
+    caller: Caller,
+    input: Rename,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    let name = db::character::prepare_name(&input.name)?;
+    let owner = if caller.has(Permission::ManageCharacters) {
+        None
+    } else {
+        Some(caller.account)
+    };
+    let status = db::rename::request(realm.chars_db.clone(), guid, owner, &name).await?;
+    Ok(warp::reply::json(&json!({ "name": name, "status": status })))
+}
+
+async fn character_force_rename_handler(
+    realm: u32,
+    guid: u32,
+    caller: Caller,
+    ctx: CtxRef,
+) -> FilterResult<impl Reply> {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require(Permission::ServiceCharacters)?;
+    db::rename::force(realm.chars_db.clone(), caller.account, guid).await?;
+    Ok(warp::reply::with_status("", StatusCode::NO_CONTENT))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AtLoginUpdate {
+    guids: Vec<u32>,
+    #[serde(default)]
+    set: Vec<String>,
+    #[serde(default)]
+    clear: Vec<String>,
+}
+
+async fn character_at_login_handler(
+    realm: u32,
+    caller: Caller,
+    input: AtLoginUpdate,
+    ctx: CtxRef,
+) -> JsonResult {
+    use db::character::AtLoginFlags;
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require(Permission::ServiceCharacters)?;
+    let set = AtLoginFlags::from_names(input.set.iter().map(String::as_str))
+        .ok_or(AppError::InvalidInput("set"))?;
+    let clear = AtLoginFlags::from_names(input.clear.iter().map(String::as_str))
+        .ok_or(AppError::InvalidInput("clear"))?;
+    if input.guids.is_empty() || (set | clear).is_empty() {
+        return Err(AppError::InvalidInput("at_login").into());
+    }
+    let data =
+        db::at_login::update(realm.chars_db.clone(), caller.account, &input.guids, set, clear)
+            .await?;
     Ok(warp::reply::json(&data))
 }
 
-async fn character_read_handler(guid: u32, ctx: CtxRef) -> JsonResult {
-    let data = db::character::read(ctx.chars_db.clone(), guid).await?;
+async fn character_audit_handler(
+    realm: u32,
+    guid: u32,
+    caller: Caller,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let caller = caller.on_realm(&ctx, realm.id).await?;
+    caller.require(Permission::ViewAudit)?;
+    let data = db::audit::list_character(realm.chars_db.clone(), guid).await?;
     Ok(warp::reply::json(&data))
 }
 
+/// Realm a route acts on, from a `/realms/{id}` prefix. Routes without one act
+/// on the main realm, as they did before there were several.
+fn realm(ctx: CtxRef) -> BoxedFilter<(u32,)> {
+    let main = ctx.main_realm().id;
+    warp::path!("realms" / u32 / ..)
+        .or(warp::any().map(move || main))
+        .unify()
+        .boxed()
+}
+
+/// Authenticated caller, anonymous requests are rejected.
+fn caller(ctx: CtxRef) -> BoxedFilter<(Caller,)> {
+    caller_body(ctx).map(|caller, _| caller).boxed()
+}
+
+/// Authenticated caller and the JSON body. The body is read along with the
+/// caller because signed requests cover it.
+fn caller_json<T>(ctx: CtxRef) -> BoxedFilter<(Caller, T)>
+where
+    T: 'static + DeserializeOwned + Send,
+{
+    caller_body(ctx)
+        .and_then(|caller: Caller, body: Bytes| async move {
+            Ok::<_, Rejection>((caller, parse_json(&body)?))
+        })
+        .untuple_one()
+        .boxed()
+}
+
+/// Authenticated caller holding `permission`.
+fn require(ctx: CtxRef, permission: Permission) -> BoxedFilter<(Caller,)> {
+    caller(ctx)
+        .and_then(move |caller: Caller| async move {
+            caller.require(permission).map_err(Rejection::from)?;
+            Ok::<_, Rejection>(caller)
+        })
+        .boxed()
+}
+
+/// Authenticated caller holding `permission`, and the JSON body.
+fn require_json<T>(ctx: CtxRef, permission: Permission) -> BoxedFilter<(Caller, T)>
+where
+    T: 'static + DeserializeOwned + Send,
+{
+    caller_json(ctx)
+        .and_then(move |caller: Caller, input: T| async move {
+            caller.require(permission).map_err(Rejection::from)?;
+            Ok::<_, Rejection>((caller, input))
+        })
+        .untuple_one()
+        .boxed()
+}
+
+/// Authenticated caller if credentials were sent. Wrong credentials are
+/// rejected rather than treated as anonymous.
+fn optional_caller(ctx: CtxRef) -> BoxedFilter<(Option<Caller>,)> {
+    optional_caller_body(ctx).map(|caller, _| caller).boxed()
+}
+
+/// Caller if credentials were sent, and the JSON body.
+fn optional_caller_json<T>(ctx: CtxRef) -> BoxedFilter<(Option<Caller>, T)>
+where
+    T: 'static + DeserializeOwned + Send,
+{
+    optional_caller_body(ctx)
+        .and_then(|caller: Option<Caller>, body: Bytes| async move {
+            Ok::<_, Rejection>((caller, parse_json(&body)?))
+        })
+        .untuple_one()
+        .boxed()
+}
+
+fn parse_json<T: DeserializeOwned>(body: &[u8]) -> FilterResult<T> {
+    serde_json::from_slice(body).map_err(|e| AppError::InvalidBody(e.to_string()).into())
+}
+
+/// Rejects requests over the rate limits of `route`, counted per client
+/// address before `filter` authenticates and per calling account after.
+fn limit<T>(
+    ctx: CtxRef,
+    route: Route,
+    filter: BoxedFilter<(Option<Caller>, T)>,
+) -> BoxedFilter<(Option<Caller>, T)>
+where
+    T: 'static + Send,
+{
+    warp::addr::remote()
+        .and(warp::header::headers_cloned())
+        .and(with(ctx.clone()))
+        .and_then(move |addr: Option<SocketAddr>, headers: HeaderMap, ctx: CtxRef| async move {
+            if !auth::signed(&headers) {
+                let ip = ctx.rate_limits.client_ip(&headers, addr.map(|addr| addr.ip()));
+                ctx.rate_limiter
+                    .check_ip(route, ctx.rate_limits.route(route), ip)
+                    .map_err(Rejection::from)?;
+            }
+            Ok::<_, Rejection>(())
+        })
+        .untuple_one()
+        .and(filter)
+        .and(with(ctx))
+        .and_then(move |caller: Option<Caller>, input: T, ctx: CtxRef| async move {
+            ctx.rate_limiter
+                .check_account(route, ctx.rate_limits.route(route), caller.as_ref())
+                .map_err(Rejection::from)?;
+            Ok::<_, Rejection>((caller, input))
+        })
+        .untuple_one()
+        .boxed()
+}
+
+fn caller_body(ctx: CtxRef) -> BoxedFilter<(Caller, Bytes)> {
+    optional_caller_body(ctx)
+        .and_then(|caller: Option<Caller>, body: Bytes| async move {
+            let caller = caller.ok_or_else(|| Rejection::from(AppError::Unauthorized))?;
+            Ok::<_, Rejection>((caller, body))
+        })
+        .untuple_one()
+        .boxed()
+}
+
+fn optional_caller_body(ctx: CtxRef) -> BoxedFilter<(Option<Caller>, Bytes)> {
+    let query = warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify();
+    warp::method()
+        .and(warp::path::full())
+        .and(query)
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and(warp::addr::remote())
+        .and(with(ctx))
+        .and_then(
+            |method: Method,
+             path: FullPath,
+             query: String,
+             headers: HeaderMap,
+             body: Bytes,
+             addr: Option<SocketAddr>,
+             ctx: CtxRef| async move {
+                let path = if query.is_empty() {
+                    path.as_str().to_owned()
+                } else {
+                    format!("{}?{}", path.as_str(), query)
+                };
+                let request = auth::Request {
+                    method: method.as_str(),
+                    path: &path,
+                    headers: &headers,
+                    body: &body,
+                    ip: ctx.rate_limits.client_ip(&headers, addr.map(|addr| addr.ip())),
+                };
+                let caller = auth::identify(&ctx, &request).await?;
+                Ok::<_, Rejection>((caller, body))
+            },
+        )
+        .untuple_one()
+        .boxed()
+}
+
+/// Hides metadata from the audience, and the game race and class along with
+/// the campaign's ones so they can't be told apart by id.
+fn redact(campaign: &Campaign, audience: Audience, character: &mut db::character::Data) {
+    fn shown(metadata: &JsonValue, field: &str) -> bool {
+        metadata.get(field).map_or(false, |v| !v.is_null())
+    }
+    let rule = campaign.visibility.rule(audience);
+    let race = shown(&character.metadata, "race");
+    let class = shown(&character.metadata, "class");
+    campaign
+        .visibility
+        .apply(campaign, audience, &mut character.metadata);
+    if rule.hide_fields.contains("race") || (race && !shown(&character.metadata, "race")) {
+        character.race = None;
+    }
+    if rule.hide_fields.contains("class") || (class && !shown(&character.metadata, "class")) {
+        character.class = None;
+    }
+}
+
 #[derive(Deserialize)]
 struct CheckName {
     name: String,
 }
 
-async fn character_check_name_handler(input: CheckName, ctx: CtxRef) -> JsonResult {
-    let data = db::character::check_name(ctx.chars_db.clone(), &input.name).await?;
+async fn character_check_name_handler(
+    realm: u32,
+    _caller: Option<Caller>,
+    input: CheckName,
+    ctx: CtxRef,
+) -> JsonResult {
+    let realm = ctx.realm(realm)?;
+    let data = db::character::check_name(realm.chars_db.clone(), &input.name).await?;
     Ok(warp::reply::json(&json!({ "result": data })))
 }